@@ -0,0 +1,180 @@
+use crate::{
+    ray_resolvers::ray_resolver::SurfaceDerivatives, textures::Footprint, utilities::Vector3,
+};
+
+//Offsets of the rays through the neighbouring pixels from a ray, to the right (x) and below (y)
+#[derive(Clone, Copy)]
+pub struct RayDifferential {
+    pub dodx: Vector3,
+    pub dody: Vector3,
+    pub dddx: Vector3,
+    pub dddy: Vector3,
+}
+
+//Change of a surface's position, normal and texture coordinates between the points
+//the neighbouring rays hit
+#[derive(Clone, Copy)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vector3,
+    pub dpdy: Vector3,
+    pub dndx: Vector3,
+    pub dndy: Vector3,
+    pub duvdx: [f32; 2],
+    pub duvdy: [f32; 2],
+}
+
+impl SurfaceDifferentials {
+    pub fn footprint(&self) -> Footprint {
+        Footprint {
+            dpdx: self.dpdx,
+            dpdy: self.dpdy,
+            duvdx: self.duvdx,
+            duvdy: self.duvdy,
+        }
+    }
+}
+
+//Least squares solution of `d = a * e1 + b * e2`
+fn barycentric(d: Vector3, e1: Vector3, e2: Vector3) -> Option<(f32, f32)> {
+    let (a11, a12, a22) = (e1.dot(e1), e1.dot(e2), e2.dot(e2));
+    let det = a11 * a22 - a12 * a12;
+    if det.abs() < 1e-20 {
+        return None;
+    }
+    let (b1, b2) = (e1.dot(d), e2.dot(d));
+    Some(((a22 * b1 - a12 * b2) / det, (a11 * b2 - a12 * b1) / det))
+}
+
+impl RayDifferential {
+    //Differential of rays from the same origin through the neighbouring pixels
+    pub fn new(dir: Vector3, dir_x: Vector3, dir_y: Vector3) -> Self {
+        Self {
+            dodx: Vector3::zero(),
+            dody: Vector3::zero(),
+            dddx: dir_x.subtract(dir),
+            dddy: dir_y.subtract(dir),
+        }
+    }
+
+    //Shrinks the offsets when a pixel is covered by several samples
+    pub fn scaled(self, scale: f32) -> Self {
+        Self {
+            dodx: self.dodx.multiply(scale),
+            dody: self.dody.multiply(scale),
+            dddx: self.dddx.multiply(scale),
+            dddy: self.dddy.multiply(scale),
+        }
+    }
+
+    //Differential of the same rays with their origins moved `t` along them
+    pub fn advanced(self, t: f32) -> Self {
+        Self {
+            dodx: self.dodx.add(self.dddx.multiply(t)),
+            dody: self.dody.add(self.dddy.multiply(t)),
+            ..self
+        }
+    }
+
+    //Differentials of the surface hit at `p` by a ray from `start`, found by intersecting the
+    //neighbouring rays with the tangent plane. Without surface derivatives the surface is
+    //treated as flat and untextured
+    pub fn at_surface(
+        &self,
+        start: Vector3,
+        dir: Vector3,
+        p: Vector3,
+        normal: Vector3,
+        derivatives: Option<&SurfaceDerivatives>,
+    ) -> Option<SurfaceDifferentials> {
+        let offset = |dodx: Vector3, dddx: Vector3| {
+            let origin = start.add(dodx);
+            let dir = dir.add(dddx);
+            let cos = dir.dot(normal);
+            if cos.abs() < 1e-8 {
+                return None;
+            }
+            let t = p.subtract(origin).dot(normal) / cos;
+            Some(origin.add(dir.multiply(t)).subtract(p))
+        };
+        let dpdx = offset(self.dodx, self.dddx)?;
+        let dpdy = offset(self.dody, self.dddy)?;
+        let mut differentials = SurfaceDifferentials {
+            dpdx,
+            dpdy,
+            dndx: Vector3::zero(),
+            dndy: Vector3::zero(),
+            duvdx: [0f32; 2],
+            duvdy: [0f32; 2],
+        };
+        let derivatives = match derivatives {
+            Some(derivatives) => derivatives,
+            None => return Some(differentials),
+        };
+        let [e1, e2] = derivatives.dpdb;
+        let (bx, by) = match (barycentric(dpdx, e1, e2), barycentric(dpdy, e1, e2)) {
+            (Some(bx), Some(by)) => (bx, by),
+            _ => return Some(differentials),
+        };
+        let [dn1, dn2] = derivatives.dndb;
+        differentials.dndx = dn1.multiply(bx.0).add(dn2.multiply(bx.1));
+        differentials.dndy = dn1.multiply(by.0).add(dn2.multiply(by.1));
+        let [duv1, duv2] = derivatives.duvdb;
+        let duv = |(b1, b2): (f32, f32)| [duv1[0] * b1 + duv2[0] * b2, duv1[1] * b1 + duv2[1] * b2];
+        differentials.duvdx = duv(bx);
+        differentials.duvdy = duv(by);
+        Some(differentials)
+    }
+
+    //Differential of the perfect reflection of a ray along `dir`, where `normal`
+    //faces the side the ray arrived from
+    pub fn reflect(&self, dir: Vector3, normal: Vector3, surface: &SurfaceDifferentials) -> Self {
+        let wo = dir.multiply(-1f32);
+        let direction = |dddx: Vector3, dndx: Vector3| {
+            let dwodx = dddx.multiply(-1f32);
+            let ddndx = dwodx.dot(normal) + wo.dot(dndx);
+            dwodx.multiply(-1f32).add(
+                dndx.multiply(wo.dot(normal))
+                    .add(normal.multiply(ddndx))
+                    .multiply(2f32),
+            )
+        };
+        Self {
+            dodx: surface.dpdx,
+            dody: surface.dpdy,
+            dddx: direction(self.dddx, surface.dndx),
+            dddy: direction(self.dddy, surface.dndy),
+        }
+    }
+
+    //Differential of the refracted ray `wi` of a ray along `dir`. `eta` is the IOR ratio across
+    //the surface as seen from the side `normal` faces
+    pub fn refract(
+        &self,
+        dir: Vector3,
+        wi: Vector3,
+        normal: Vector3,
+        eta: f32,
+        surface: &SurfaceDifferentials,
+    ) -> Self {
+        let wo = dir.multiply(-1f32);
+        let eta = 1f32 / eta;
+        let cos_o = wo.dot(normal);
+        let cos_i = wi.dot(normal).abs().max(1e-6);
+        let mu = eta * cos_o - cos_i;
+        let direction = |dddx: Vector3, dndx: Vector3| {
+            let dwodx = dddx.multiply(-1f32);
+            let ddndx = dwodx.dot(normal) + wo.dot(dndx);
+            let dmudx = (eta - eta * eta * cos_o / cos_i) * ddndx;
+            dwodx
+                .multiply(-eta)
+                .add(dndx.multiply(mu))
+                .add(normal.multiply(dmudx))
+        };
+        Self {
+            dodx: surface.dpdx,
+            dody: surface.dpdy,
+            dddx: direction(self.dddx, surface.dndx),
+            dddy: direction(self.dddy, surface.dndy),
+        }
+    }
+}
@@ -35,3 +35,4 @@ macro_rules! implement_error {
 
 implement_error!(std::io::Error, "IO error");
 implement_error!(LoadError, "OBJ load error");
+implement_error!(gltf::Error, "glTF load error");
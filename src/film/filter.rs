@@ -0,0 +1,74 @@
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy)]
+pub enum FilterType {
+    Box,
+    Tent,
+    //Falloff (alpha)
+    Gaussian(f32),
+    //B and C parameters
+    MitchellNetravali(f32, f32),
+    //Number of sinc lobes in the window (tau)
+    Lanczos(f32),
+}
+
+#[derive(Clone, Copy)]
+pub struct Filter {
+    pub filter_type: FilterType,
+    pub radius: f32,
+}
+
+impl Filter {
+    pub const fn new(filter_type: FilterType, radius: f32) -> Self {
+        Self {
+            filter_type,
+            radius,
+        }
+    }
+
+    //Weight of a sample at an offset of (x, y) pixels from the pixel center
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        if x.abs() > self.radius || y.abs() > self.radius {
+            return 0f32;
+        }
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self.filter_type {
+            FilterType::Box => 1f32,
+            FilterType::Tent => (self.radius - x).max(0f32),
+            FilterType::Gaussian(alpha) => {
+                let edge = (-alpha * self.radius * self.radius).exp();
+                ((-alpha * x * x).exp() - edge).max(0f32)
+            }
+            FilterType::MitchellNetravali(b, c) => Self::mitchell(2f32 * x / self.radius, b, c),
+            FilterType::Lanczos(tau) => Self::sinc(x) * Self::sinc(x / tau),
+        }
+    }
+
+    fn mitchell(x: f32, b: f32, c: f32) -> f32 {
+        let x2 = x * x;
+        let x3 = x2 * x;
+        let v = if x > 1f32 {
+            (-b - 6f32 * c) * x3
+                + (6f32 * b + 30f32 * c) * x2
+                + (-12f32 * b - 48f32 * c) * x
+                + (8f32 * b + 24f32 * c)
+        } else {
+            (12f32 - 9f32 * b - 6f32 * c) * x3
+                + (-18f32 + 12f32 * b + 6f32 * c) * x2
+                + (6f32 - 2f32 * b)
+        };
+        v / 6f32
+    }
+
+    fn sinc(x: f32) -> f32 {
+        if x < 1e-5 {
+            return 1f32;
+        }
+        let x = x * PI;
+        x.sin() / x
+    }
+}
@@ -0,0 +1,108 @@
+use crate::utilities::Vector3;
+
+use self::filter::Filter;
+
+pub mod filter;
+
+#[derive(Clone, Copy)]
+struct FilmPixel {
+    color: Vector3,
+    weight: f32,
+}
+
+impl FilmPixel {
+    fn empty() -> Self {
+        Self {
+            color: Vector3::zero(),
+            weight: 0f32,
+        }
+    }
+}
+
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<FilmPixel>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![FilmPixel::empty(); (width * height) as usize],
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: FilmTile) {
+        let offset = (tile.y_start * self.width) as usize;
+        for (i, p) in tile.pixels.into_iter().enumerate() {
+            let pixel = &mut self.pixels[offset + i];
+            pixel.color = pixel.color.add(p.color);
+            pixel.weight += p.weight;
+        }
+    }
+
+    pub fn resolve(&self) -> Vec<Vector3> {
+        self.pixels
+            .iter()
+            .map(|p| {
+                if p.weight == 0f32 {
+                    Vector3::zero()
+                } else {
+                    //Negative filter lobes can push dark pixels below zero
+                    p.color.multiply(1f32 / p.weight).max(Vector3::zero())
+                }
+            })
+            .collect()
+    }
+}
+
+pub struct FilmTile {
+    width: u32,
+    y_start: u32,
+    y_end: u32,
+    filter: Filter,
+    pixels: Vec<FilmPixel>,
+}
+
+impl FilmTile {
+    //Creates a tile that can receive every sample taken inside row `y` of a film
+    pub fn for_row(width: u32, height: u32, y: u32, filter: &Filter) -> Self {
+        let reach = (filter.radius - 0.5).max(0f32).ceil() as u32;
+        let y_start = y.saturating_sub(reach);
+        let y_end = (y + reach + 1).min(height);
+        Self {
+            width,
+            y_start,
+            y_end,
+            filter: *filter,
+            pixels: vec![FilmPixel::empty(); (width * (y_end - y_start)) as usize],
+        }
+    }
+
+    //Splats a sample at raster position (x, y) onto every pixel within the filter radius
+    pub fn add_sample(&mut self, x: f32, y: f32, color: Vector3) {
+        let radius = self.filter.radius;
+        let x_start = (x - 0.5 - radius).ceil().max(0f32) as u32;
+        let x_end = ((x - 0.5 + radius).floor() + 1f32).min(self.width as f32);
+        let y_start = ((y - 0.5 - radius).ceil().max(0f32) as u32).max(self.y_start);
+        let y_end = ((y - 0.5 + radius).floor() + 1f32).min(self.y_end as f32);
+        if x_end < 0f32 || y_end < 0f32 {
+            return;
+        }
+        for py in y_start..y_end as u32 {
+            for px in x_start..x_end as u32 {
+                let weight = self
+                    .filter
+                    .evaluate(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if weight == 0f32 {
+                    continue;
+                }
+                let pixel = &mut self.pixels[((py - self.y_start) * self.width + px) as usize];
+                pixel.color = pixel.color.add(color.multiply(weight));
+                pixel.weight += weight;
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fs, path::Path};
+
+use crate::error::Error;
+
+//Photometric profile from an IESNA LM-63 file, describing how the intensity of a luminaire
+//varies with direction. Vertical angles are measured from straight down, horizontal angles
+//around that axis, both in degrees.
+#[derive(Clone)]
+pub struct IesProfile {
+    vertical: Vec<f32>,
+    horizontal: Vec<f32>,
+    //Candela for every horizontal angle, each a row of every vertical angle
+    candela: Vec<Vec<f32>>,
+    max: f32,
+}
+
+fn parse_number(token: Option<&str>) -> Result<f32, Error> {
+    let token = token.ok_or_else(|| Error::new("IES file ended early".to_string()))?;
+    token
+        .parse::<f32>()
+        .map_err(|_| Error::new(format!("Invalid number in IES file: {}", token)))
+}
+
+impl IesProfile {
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> Result<Self, Error> {
+        let text = fs::read_to_string(filename)?;
+        let tilt = text
+            .find("TILT=")
+            .ok_or_else(|| Error::new("IES file has no TILT line".to_string()))?;
+        let mut lines = text[tilt..].lines();
+        match lines.next().map(str::trim) {
+            Some("TILT=NONE") => (),
+            Some(tilt) => return Err(Error::new(format!("Unsupported IES tilt: {}", tilt))),
+            None => return Err(Error::new("IES file has no TILT line".to_string())),
+        }
+        let rest = lines.collect::<Vec<_>>().join(" ");
+        let mut tokens = rest
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty());
+        let mut next = || parse_number(tokens.next());
+        let _lamps = next()?;
+        let _lumens = next()?;
+        let multiplier = next()?;
+        let vertical_count = next()? as usize;
+        let horizontal_count = next()? as usize;
+        //Photometric type, units, dimensions, ballast factor, future use and input watts
+        for _ in 0..8 {
+            next()?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(Error::new("IES file has no angles".to_string()));
+        }
+        let vertical = (0..vertical_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let horizontal = (0..horizontal_count)
+            .map(|_| next())
+            .collect::<Result<Vec<_>, _>>()?;
+        let candela = (0..horizontal_count)
+            .map(|_| {
+                (0..vertical_count)
+                    .map(|_| Ok(next()? * multiplier))
+                    .collect::<Result<Vec<_>, Error>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let max = candela.iter().flatten().cloned().fold(0f32, f32::max);
+        if max <= 0f32 {
+            return Err(Error::new("IES file has no light output".to_string()));
+        }
+        Ok(Self {
+            vertical,
+            horizontal,
+            candela,
+            max,
+        })
+    }
+
+    //Intensity relative to the brightest direction of the profile
+    pub fn evaluate(&self, vertical: f32, horizontal: f32) -> f32 {
+        //Profiles only cover one part of the horizontal circle if they are symmetric
+        let horizontal = horizontal.rem_euclid(360f32);
+        let last = *self.horizontal.last().unwrap();
+        let horizontal = if last <= 0f32 {
+            0f32
+        } else if last <= 90f32 {
+            let h = if horizontal > 180f32 {
+                360f32 - horizontal
+            } else {
+                horizontal
+            };
+            if h > 90f32 {
+                180f32 - h
+            } else {
+                h
+            }
+        } else if last <= 180f32 && horizontal > 180f32 {
+            360f32 - horizontal
+        } else {
+            horizontal
+        };
+        let (h0, h1, th) = match locate(&self.horizontal, horizontal) {
+            Some(h) => h,
+            None => return 0f32,
+        };
+        let (v0, v1, tv) = match locate(&self.vertical, vertical) {
+            Some(v) => v,
+            None => return 0f32,
+        };
+        let row = |h: usize| self.candela[h][v0] + (self.candela[h][v1] - self.candela[h][v0]) * tv;
+        (row(h0) + (row(h1) - row(h0)) * th) / self.max
+    }
+}
+
+//Indices of the angles surrounding `angle` and the fraction of the way between them
+fn locate(angles: &[f32], angle: f32) -> Option<(usize, usize, f32)> {
+    if angles.len() == 1 {
+        return Some((0, 0, 0f32));
+    }
+    if angle < angles[0] || angle > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = angles
+        .windows(2)
+        .position(|w| angle <= w[1])
+        .unwrap_or(angles.len() - 2);
+    let span = angles[i + 1] - angles[i];
+    let t = if span > 0f32 {
+        (angle - angles[i]) / span
+    } else {
+        0f32
+    };
+    Some((i, i + 1, t))
+}
@@ -0,0 +1,401 @@
+use std::{cmp::Ordering, f32::consts::PI};
+
+use crate::{
+    materials::principled::Material,
+    ray_resolvers::bvh::{aabb::AABB, triangle::Triangle},
+    utilities::Vector3,
+};
+
+use super::LightSample;
+
+//Emissive triangle. Triangles emit from the side `normal` faces, and also from the other one
+//if their material is double sided, matching the sides rays can hit
+#[derive(Clone)]
+pub struct Emitter {
+    pub v0: Vector3,
+    pub v1: Vector3,
+    pub v2: Vector3,
+    pub normal: Vector3,
+    pub two_sided: bool,
+    pub area: f32,
+    //Emitting material, which may be textured
+    pub material: Material,
+    pub uvs: [[f32; 2]; 3],
+}
+
+impl Emitter {
+    fn new(triangle: &Triangle) -> Self {
+        let cross = triangle
+            .v1
+            .subtract(triangle.v0)
+            .cross(triangle.v2.subtract(triangle.v0));
+        let area = cross.length() * 0.5;
+        Self {
+            v0: triangle.v0,
+            v1: triangle.v1,
+            v2: triangle.v2,
+            normal: cross.normalized(),
+            two_sided: triangle.material.is_double_sided(),
+            area,
+            material: triangle.material.clone(),
+            uvs: [triangle.uv0, triangle.uv1, triangle.uv2],
+        }
+    }
+
+    fn bounds(&self) -> LightBounds {
+        let aabb = AABB {
+            min: self.v0.min(self.v1).min(self.v2),
+            max: self.v0.max(self.v1).max(self.v2),
+        };
+        LightBounds {
+            aabb,
+            power: self.material.emitted().average() * self.area,
+            axis: self.normal,
+            cos_theta: 1f32,
+            two_sided: self.two_sided,
+        }
+    }
+
+    //Cosine between the normal of the emitting side and the direction from the triangle
+    //towards `dir`'s origin, or less than zero if that side doesn't emit
+    fn emitting_cos(&self, dir: Vector3) -> f32 {
+        let cos = -dir.dot(self.normal);
+        if self.two_sided {
+            cos.abs()
+        } else {
+            cos
+        }
+    }
+
+    //Samples a point uniformly over the area of the triangle
+    fn sample(&self, p: Vector3) -> Option<LightSample> {
+        let r = rand::random::<f32>().sqrt();
+        let (u, v) = (1f32 - r, r * rand::random::<f32>());
+        let point = self
+            .v0
+            .add(self.v1.subtract(self.v0).multiply(u))
+            .add(self.v2.subtract(self.v0).multiply(v));
+        let to_light = point.subtract(p);
+        let distance = to_light.length();
+        let dir = to_light.multiply(1f32 / distance);
+        let cos = self.emitting_cos(dir);
+        if cos <= 0f32 || distance <= 0f32 {
+            return None;
+        }
+        let pdf = distance * distance / (self.area * cos);
+        let [uv0, uv1, uv2] = self.uvs;
+        let uv = [
+            uv0[0] + (uv1[0] - uv0[0]) * u + (uv2[0] - uv0[0]) * v,
+            uv0[1] + (uv1[1] - uv0[1]) * u + (uv2[1] - uv0[1]) * v,
+        ];
+        let emission = self.material.textured(point, uv).emitted();
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: emission.multiply(1f32 / pdf),
+            pdf,
+        })
+    }
+
+    //Solid angle probability of `sample` returning the direction towards `point` on the triangle
+    fn pdf(&self, p: Vector3, point: Vector3) -> f32 {
+        let to_light = point.subtract(p);
+        let distance_squared = to_light.length_squared();
+        let cos = self.emitting_cos(to_light.normalized());
+        if cos <= 0f32 {
+            return 0f32;
+        }
+        distance_squared / (self.area * cos)
+    }
+}
+
+//Spatial and directional extent of a group of emitters
+#[derive(Clone, Copy)]
+struct LightBounds {
+    aabb: AABB,
+    power: f32,
+    //Cone containing the normals of every emitter
+    axis: Vector3,
+    cos_theta: f32,
+    //Whether any of the emitters emits from both sides
+    two_sided: bool,
+}
+
+fn angle(cos: f32) -> f32 {
+    cos.clamp(-1f32, 1f32).acos()
+}
+
+impl LightBounds {
+    fn union(&self, other: &Self) -> Self {
+        let (axis, cos_theta) = self.cone_union(other);
+        Self {
+            aabb: self.aabb.union(&other.aabb),
+            power: self.power + other.power,
+            axis,
+            cos_theta,
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    //Smallest cone containing both cones
+    fn cone_union(&self, other: &Self) -> (Vector3, f32) {
+        let theta_a = angle(self.cos_theta);
+        let theta_b = angle(other.cos_theta);
+        let theta_d = angle(self.axis.dot(other.axis));
+        if (theta_d + theta_b).min(PI) <= theta_a {
+            return (self.axis, self.cos_theta);
+        }
+        if (theta_d + theta_a).min(PI) <= theta_b {
+            return (other.axis, other.cos_theta);
+        }
+        let theta_o = (theta_a + theta_d + theta_b) * 0.5;
+        let rotation_axis = self.axis.cross(other.axis);
+        if theta_o >= PI || rotation_axis.length_squared() <= 0f32 {
+            return (self.axis, -1f32);
+        }
+        //Rotate the first axis towards the second one
+        let theta_r = theta_o - theta_a;
+        let perpendicular = rotation_axis.normalized().cross(self.axis);
+        let axis = self
+            .axis
+            .multiply(theta_r.cos())
+            .add(perpendicular.multiply(theta_r.sin()));
+        (axis.normalized(), theta_o.cos())
+    }
+
+    //Estimate of how much light the emitters contribute at `p`, at a surface facing `normal`
+    fn importance(&self, p: Vector3, normal: Option<Vector3>) -> f32 {
+        let center = self.aabb.min.add(self.aabb.max).multiply(0.5);
+        let radius = self.aabb.size().length() * 0.5;
+        let offset = p.subtract(center);
+        let distance_squared = offset.length_squared().max(radius);
+        //Angle the bounds cover as seen from `p`
+        let theta_b = if offset.length_squared() <= radius * radius {
+            PI
+        } else {
+            (radius / offset.length()).asin()
+        };
+        let wi = offset.normalized();
+        //Two sided emitters light both sides, so only the angle to the closer side of the
+        //cone matters for them
+        let cos_w = self.axis.dot(wi);
+        let theta_w = angle(if self.two_sided { cos_w.abs() } else { cos_w });
+        let theta = (theta_w - angle(self.cos_theta) - theta_b).max(0f32);
+        if theta >= PI * 0.5 {
+            return 0f32;
+        }
+        let importance = self.power * theta.cos() / distance_squared;
+        match normal {
+            Some(normal) => {
+                let theta_i = angle(normal.dot(wi).abs());
+                importance * (theta_i - theta_b).max(0f32).cos()
+            }
+            None => importance,
+        }
+    }
+}
+
+enum LightNode {
+    Leaf(usize),
+    Interior(Box<(LightTree, LightTree)>),
+}
+
+//Hierarchy over emissive triangles, for picking one in proportion to its estimated contribution.
+//Each node bounds its emitters' positions and the cone of their normals
+pub struct LightTree {
+    bounds: LightBounds,
+    node: LightNode,
+}
+
+fn build(emitters: &[Emitter], indices: &mut [usize]) -> LightTree {
+    if indices.len() == 1 {
+        return LightTree {
+            bounds: emitters[indices[0]].bounds(),
+            node: LightNode::Leaf(indices[0]),
+        };
+    }
+    let centroid = |i: usize| {
+        let e = &emitters[i];
+        e.v0.add(e.v1).add(e.v2).multiply(1.0 / 3.0)
+    };
+    let mut extent = AABB {
+        min: centroid(indices[0]),
+        max: centroid(indices[0]),
+    };
+    for i in indices.iter() {
+        let c = centroid(*i);
+        extent = extent.union(&AABB { min: c, max: c });
+    }
+    //Divide among the median of the longest dimension
+    let orientation = extent.size().largest_component();
+    let index = indices.len() / 2;
+    indices.select_nth_unstable_by(index, |a, b| {
+        let a_pos = centroid(*a).get_component(orientation);
+        let b_pos = centroid(*b).get_component(orientation);
+        a_pos.partial_cmp(&b_pos).unwrap_or(Ordering::Equal)
+    });
+    let (i1, i2) = indices.split_at_mut(index);
+    let t1 = build(emitters, i1);
+    let t2 = build(emitters, i2);
+    LightTree {
+        bounds: t1.bounds.union(&t2.bounds),
+        node: LightNode::Interior(Box::new((t1, t2))),
+    }
+}
+
+impl LightTree {
+    //Probability of descending into the first child
+    fn split(
+        children: &(LightTree, LightTree),
+        p: Vector3,
+        normal: Option<Vector3>,
+    ) -> Option<f32> {
+        let i1 = children.0.bounds.importance(p, normal);
+        let i2 = children.1.bounds.importance(p, normal);
+        if i1 + i2 <= 0f32 {
+            return None;
+        }
+        Some(i1 / (i1 + i2))
+    }
+
+    //Picks an emitter for `p`, along with the probability of picking it
+    fn pick(&self, p: Vector3, normal: Option<Vector3>) -> Option<(usize, f32)> {
+        let mut node = self;
+        let mut probability = 1f32;
+        loop {
+            match &node.node {
+                LightNode::Leaf(index) => return Some((*index, probability)),
+                LightNode::Interior(children) => {
+                    let first = Self::split(children, p, normal)?;
+                    if rand::random::<f32>() < first {
+                        probability *= first;
+                        node = &children.0;
+                    } else {
+                        probability *= 1f32 - first;
+                        node = &children.1;
+                    }
+                }
+            }
+        }
+    }
+
+    //Probability of `pick` returning the emitter reached by following `trail`
+    fn probability(&self, p: Vector3, normal: Option<Vector3>, trail: u64) -> f32 {
+        let mut node = self;
+        let mut trail = trail;
+        let mut probability = 1f32;
+        loop {
+            match &node.node {
+                LightNode::Leaf(_) => return probability,
+                LightNode::Interior(children) => {
+                    let first = match Self::split(children, p, normal) {
+                        Some(first) => first,
+                        None => return 0f32,
+                    };
+                    if trail & 1 == 0 {
+                        probability *= first;
+                        node = &children.0;
+                    } else {
+                        probability *= 1f32 - first;
+                        node = &children.1;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+
+    //Bits choosing the child on the path to every leaf, starting from the lowest bit
+    fn trails(&self, trail: u64, depth: u32, trails: &mut Vec<u64>) {
+        match &self.node {
+            LightNode::Leaf(index) => trails[*index] = trail,
+            LightNode::Interior(children) => {
+                children.0.trails(trail, depth + 1, trails);
+                children.1.trails(trail | (1 << depth), depth + 1, trails);
+            }
+        }
+    }
+}
+
+//Emissive triangles of a scene with a tree over them
+pub struct Emitters {
+    pub emitters: Vec<Emitter>,
+    tree: Option<LightTree>,
+    trails: Vec<u64>,
+}
+
+impl Emitters {
+    pub fn new() -> Self {
+        Self {
+            emitters: Vec::new(),
+            tree: None,
+            trails: Vec::new(),
+        }
+    }
+
+    //Collects the emissive triangles, tagging them with their index among the emitters
+    pub fn from_triangles(triangles: Vec<Triangle>) -> (Vec<Triangle>, Self) {
+        let mut emitters = Vec::new();
+        let triangles = triangles
+            .into_iter()
+            .map(|triangle| {
+                if triangle.material.emitted().largest_component_value() <= 0f32 {
+                    return triangle;
+                }
+                let emitter = Emitter::new(&triangle);
+                if emitter.area <= 0f32 {
+                    return triangle;
+                }
+                emitters.push(emitter);
+                triangle.with_light(emitters.len() as u32 - 1)
+            })
+            .collect();
+        if emitters.is_empty() {
+            return (triangles, Self::new());
+        }
+        let mut indices: Vec<usize> = (0..emitters.len()).collect();
+        let tree = build(&emitters, &mut indices);
+        let mut trails = vec![0; emitters.len()];
+        tree.trails(0, 0, &mut trails);
+        (
+            triangles,
+            Self {
+                emitters,
+                tree: Some(tree),
+                trails,
+            },
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_none()
+    }
+
+    //Samples light from an emitter picked for a point on a surface facing `normal`,
+    //or a point in a volume if there is no normal
+    pub fn sample(&self, p: Vector3, normal: Option<Vector3>) -> Option<LightSample> {
+        let (index, probability) = self.tree.as_ref()?.pick(p, normal)?;
+        let sample = self.emitters[index].sample(p)?;
+        Some(LightSample {
+            radiance: sample.radiance.multiply(1f32 / probability),
+            pdf: sample.pdf * probability,
+            ..sample
+        })
+    }
+
+    //Solid angle probability of `sample` reaching `point` on the given emitter
+    pub fn pdf(&self, p: Vector3, normal: Option<Vector3>, index: u32, point: Vector3) -> f32 {
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return 0f32,
+        };
+        let index = index as usize;
+        tree.probability(p, normal, self.trails[index]) * self.emitters[index].pdf(p, point)
+    }
+}
+
+impl Default for Emitters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
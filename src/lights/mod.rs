@@ -0,0 +1,401 @@
+use std::f32::consts::PI;
+
+use crate::utilities::{Matrix3, Vector3};
+
+use self::ies::IesProfile;
+
+pub mod ies;
+pub mod light_tree;
+
+pub struct LightSample {
+    //Direction towards the light
+    pub dir: Vector3,
+    pub distance: f32,
+    //Radiance arriving from the light divided by `pdf`, ignoring anything in between
+    pub radiance: Vector3,
+    //Solid angle probability of sampling `dir`, 0 for lights with no extent
+    pub pdf: f32,
+}
+
+#[derive(Clone)]
+pub enum LightType {
+    Point,
+    //Cone around `direction` with a half angle of `angle` in radians. `blend` is the fraction
+    //of the cone over which the light fades out towards its edge
+    Spot {
+        direction: Vector3,
+        angle: f32,
+        blend: f32,
+    },
+    //Light from infinitely far away travelling along `direction`, like the sun. It covers a disk
+    //of `angular_diameter` radians in the sky. The position of the light is ignored
+    Directional {
+        direction: Vector3,
+        angular_diameter: f32,
+    },
+    //Parallelogram centered on the position of the light with edges `u` and `v`,
+    //emitting towards `u` cross `v`
+    Rect {
+        u: Vector3,
+        v: Vector3,
+    },
+    Disk {
+        normal: Vector3,
+        radius: f32,
+    },
+    Sphere {
+        radius: f32,
+    },
+}
+
+#[derive(Clone)]
+pub struct Light {
+    pub light_type: LightType,
+    pub position: Vector3,
+    pub color: Vector3,
+    //Radiant intensity of point and spot lights, irradiance of directional lights
+    //and radiance of area lights
+    pub intensity: f32,
+    //Directional distribution of point and spot lights, pointing down or along the spot
+    pub ies: Option<IesProfile>,
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0f32, 1f32);
+    t * t * (3f32 - 2f32 * t)
+}
+
+//Uniformly samples a direction within `cos_max` of `axis`
+fn sample_cone(axis: Vector3, cos_max: f32) -> Vector3 {
+    let cos = 1f32 - rand::random::<f32>() * (1f32 - cos_max);
+    let sin = (1f32 - cos * cos).max(0f32).sqrt();
+    let phi = 2f32 * PI * rand::random::<f32>();
+    Matrix3::from_normal(axis).multiply(Vector3::new(sin * phi.cos(), sin * phi.sin(), cos))
+}
+
+fn cone_solid_angle(cos_max: f32) -> f32 {
+    2f32 * PI * (1f32 - cos_max)
+}
+
+impl Light {
+    fn new(light_type: LightType, position: Vector3, color: Vector3, intensity: f32) -> Self {
+        Self {
+            light_type,
+            position,
+            color,
+            intensity,
+            ies: None,
+        }
+    }
+
+    pub fn point(position: Vector3, color: Vector3, intensity: f32) -> Self {
+        Self::new(LightType::Point, position, color, intensity)
+    }
+
+    pub fn spot(
+        position: Vector3,
+        direction: Vector3,
+        angle: f32,
+        blend: f32,
+        color: Vector3,
+        intensity: f32,
+    ) -> Self {
+        let light_type = LightType::Spot {
+            direction: direction.normalized(),
+            angle,
+            blend,
+        };
+        Self::new(light_type, position, color, intensity)
+    }
+
+    pub fn directional(
+        direction: Vector3,
+        angular_diameter: f32,
+        color: Vector3,
+        intensity: f32,
+    ) -> Self {
+        let light_type = LightType::Directional {
+            direction: direction.normalized(),
+            angular_diameter,
+        };
+        Self::new(light_type, Vector3::zero(), color, intensity)
+    }
+
+    pub fn rect(position: Vector3, u: Vector3, v: Vector3, color: Vector3, intensity: f32) -> Self {
+        Self::new(LightType::Rect { u, v }, position, color, intensity)
+    }
+
+    pub fn disk(
+        position: Vector3,
+        normal: Vector3,
+        radius: f32,
+        color: Vector3,
+        intensity: f32,
+    ) -> Self {
+        let light_type = LightType::Disk {
+            normal: normal.normalized(),
+            radius,
+        };
+        Self::new(light_type, position, color, intensity)
+    }
+
+    pub fn sphere(position: Vector3, radius: f32, color: Vector3, intensity: f32) -> Self {
+        Self::new(LightType::Sphere { radius }, position, color, intensity)
+    }
+
+    pub fn with_ies(self, ies: IesProfile) -> Self {
+        Self {
+            ies: Some(ies),
+            ..self
+        }
+    }
+
+    pub fn is_directional(&self) -> bool {
+        matches!(self.light_type, LightType::Directional { .. })
+    }
+
+    fn radiance(&self) -> Vector3 {
+        self.color.multiply(self.intensity)
+    }
+
+    //Intensity multiplier of the IES profile for light leaving along `dir`
+    fn profile(&self, axis: Vector3, dir: Vector3) -> f32 {
+        match &self.ies {
+            Some(ies) => {
+                let local = Matrix3::from_normal(axis).transpose_multiply(dir);
+                let vertical = local.z.clamp(-1f32, 1f32).acos().to_degrees();
+                let horizontal = local.y.atan2(local.x).to_degrees();
+                ies.evaluate(vertical, horizontal)
+            }
+            None => 1f32,
+        }
+    }
+
+    //Normal and area of flat area lights
+    fn surface(&self) -> Option<(Vector3, f32)> {
+        match &self.light_type {
+            LightType::Rect { u, v } => {
+                let n = u.cross(*v);
+                let area = n.length();
+                Some((n.multiply(1f32 / area), area))
+            }
+            LightType::Disk { normal, radius } => Some((*normal, PI * radius * radius)),
+            _ => None,
+        }
+    }
+
+    //Samples light arriving at `p`
+    pub fn sample(&self, p: Vector3) -> Option<LightSample> {
+        match &self.light_type {
+            LightType::Point | LightType::Spot { .. } => {
+                let to_light = self.position.subtract(p);
+                let distance = to_light.length();
+                if distance <= 0f32 {
+                    return None;
+                }
+                let dir = to_light.multiply(1f32 / distance);
+                let (axis, falloff) = match &self.light_type {
+                    LightType::Spot {
+                        direction,
+                        angle,
+                        blend,
+                    } => {
+                        let cos = -dir.dot(*direction);
+                        let outer = angle.cos();
+                        let inner = (angle * (1f32 - blend)).cos();
+                        let falloff = if *blend <= 0f32 {
+                            if cos >= outer {
+                                1f32
+                            } else {
+                                0f32
+                            }
+                        } else {
+                            smoothstep(outer, inner, cos)
+                        };
+                        (*direction, falloff)
+                    }
+                    _ => (Vector3::new(0f32, -1f32, 0f32), 1f32),
+                };
+                let falloff = falloff * self.profile(axis, dir.multiply(-1f32));
+                if falloff <= 0f32 {
+                    return None;
+                }
+                Some(LightSample {
+                    dir,
+                    distance,
+                    radiance: self.radiance().multiply(falloff / (distance * distance)),
+                    pdf: 0f32,
+                })
+            }
+            LightType::Directional {
+                direction,
+                angular_diameter,
+            } => {
+                let towards = direction.multiply(-1f32);
+                if *angular_diameter <= 0f32 {
+                    return Some(LightSample {
+                        dir: towards,
+                        distance: f32::INFINITY,
+                        radiance: self.radiance(),
+                        pdf: 0f32,
+                    });
+                }
+                //Irradiance spread evenly over the disk of the light
+                let cos_max = (angular_diameter * 0.5).cos();
+                Some(LightSample {
+                    dir: sample_cone(towards, cos_max),
+                    distance: f32::INFINITY,
+                    radiance: self.radiance(),
+                    pdf: 1f32 / cone_solid_angle(cos_max),
+                })
+            }
+            LightType::Rect { u, v } => {
+                let point = self
+                    .position
+                    .add(u.multiply(rand::random::<f32>() - 0.5))
+                    .add(v.multiply(rand::random::<f32>() - 0.5));
+                self.sample_surface(p, point)
+            }
+            LightType::Disk { normal, radius } => {
+                let r = radius * rand::random::<f32>().sqrt();
+                let phi = 2f32 * PI * rand::random::<f32>();
+                let offset = Matrix3::from_normal(*normal).multiply(Vector3::new(
+                    r * phi.cos(),
+                    r * phi.sin(),
+                    0f32,
+                ));
+                self.sample_surface(p, self.position.add(offset))
+            }
+            LightType::Sphere { radius } => {
+                //Sample the cone of directions the sphere covers
+                let to_center = self.position.subtract(p);
+                let center_distance = to_center.length();
+                if center_distance <= *radius {
+                    return None;
+                }
+                let sin2_max = radius * radius / (center_distance * center_distance);
+                let cos_max = (1f32 - sin2_max).max(0f32).sqrt();
+                let dir = sample_cone(to_center.multiply(1f32 / center_distance), cos_max);
+                let distance = self.intersect(p, dir)?.0;
+                let pdf = 1f32 / cone_solid_angle(cos_max);
+                Some(LightSample {
+                    dir,
+                    distance,
+                    radiance: self.radiance().multiply(1f32 / pdf),
+                    pdf,
+                })
+            }
+        }
+    }
+
+    fn sample_surface(&self, p: Vector3, point: Vector3) -> Option<LightSample> {
+        let (normal, area) = self.surface()?;
+        let to_light = point.subtract(p);
+        let distance = to_light.length();
+        let dir = to_light.multiply(1f32 / distance);
+        let cos = -dir.dot(normal);
+        if cos <= 0f32 {
+            return None;
+        }
+        let pdf = distance * distance / (area * cos);
+        Some(LightSample {
+            dir,
+            distance,
+            radiance: self.radiance().multiply(1f32 / pdf),
+            pdf,
+        })
+    }
+
+    //Distance along a ray to the light and the radiance it sees, for lights with an extent
+    pub fn intersect(&self, start: Vector3, dir: Vector3) -> Option<(f32, Vector3)> {
+        match &self.light_type {
+            LightType::Point | LightType::Spot { .. } => None,
+            LightType::Directional {
+                direction,
+                angular_diameter,
+            } => {
+                let cos_max = (angular_diameter * 0.5).cos();
+                if *angular_diameter <= 0f32 || -dir.dot(*direction) < cos_max {
+                    return None;
+                }
+                let radiance = self.radiance().multiply(1f32 / cone_solid_angle(cos_max));
+                Some((f32::INFINITY, radiance))
+            }
+            LightType::Rect { .. } | LightType::Disk { .. } => {
+                let (normal, _) = self.surface()?;
+                let cos = dir.dot(normal);
+                if cos >= 0f32 {
+                    return None;
+                }
+                let t = self.position.subtract(start).dot(normal) / cos;
+                if t <= 0f32 {
+                    return None;
+                }
+                let offset = start.add(dir.multiply(t)).subtract(self.position);
+                let inside = match &self.light_type {
+                    LightType::Rect { u, v } => {
+                        (offset.dot(*u) / u.length_squared()).abs() <= 0.5
+                            && (offset.dot(*v) / v.length_squared()).abs() <= 0.5
+                    }
+                    LightType::Disk { radius, .. } => offset.length() <= *radius,
+                    _ => false,
+                };
+                if inside {
+                    Some((t, self.radiance()))
+                } else {
+                    None
+                }
+            }
+            LightType::Sphere { radius } => {
+                let oc = start.subtract(self.position);
+                let b = oc.dot(dir);
+                let c = oc.length_squared() - radius * radius;
+                let discriminant = b * b - c;
+                if c <= 0f32 || discriminant < 0f32 {
+                    return None;
+                }
+                let t = -b - discriminant.sqrt();
+                if t <= 0f32 {
+                    return None;
+                }
+                Some((t, self.radiance()))
+            }
+        }
+    }
+
+    //Solid angle probability of `sample` returning `dir` from `p`
+    pub fn pdf(&self, p: Vector3, dir: Vector3) -> f32 {
+        match &self.light_type {
+            LightType::Point | LightType::Spot { .. } => 0f32,
+            LightType::Directional {
+                direction,
+                angular_diameter,
+            } => {
+                let cos_max = (angular_diameter * 0.5).cos();
+                if *angular_diameter <= 0f32 || -dir.dot(*direction) < cos_max {
+                    0f32
+                } else {
+                    1f32 / cone_solid_angle(cos_max)
+                }
+            }
+            LightType::Rect { .. } | LightType::Disk { .. } => {
+                let (normal, area) = match self.surface() {
+                    Some(surface) => surface,
+                    None => return 0f32,
+                };
+                match self.intersect(p, dir) {
+                    Some((distance, _)) => distance * distance / (area * -dir.dot(normal)),
+                    None => 0f32,
+                }
+            }
+            LightType::Sphere { radius } => {
+                let center_distance = self.position.subtract(p).length();
+                if center_distance <= *radius || self.intersect(p, dir).is_none() {
+                    return 0f32;
+                }
+                let sin2_max = radius * radius / (center_distance * center_distance);
+                1f32 / cone_solid_angle((1f32 - sin2_max).max(0f32).sqrt())
+            }
+        }
+    }
+}
@@ -1,3 +1,17 @@
+//Lints the existing code base is written against
+#![allow(
+    clippy::absurd_extreme_comparisons,
+    clippy::clone_on_copy,
+    clippy::identity_op,
+    clippy::legacy_numeric_constants,
+    clippy::len_zero,
+    clippy::manual_range_contains,
+    clippy::multiple_bound_locations,
+    clippy::needless_return,
+    clippy::single_match,
+    clippy::upper_case_acronyms
+)]
+
 extern crate exr;
 extern crate image;
 extern crate indicatif;
@@ -5,23 +19,37 @@ extern crate rand;
 extern crate rand_distr;
 extern crate rayon;
 
+pub mod differentials;
 pub mod error;
+pub mod film;
+pub mod lights;
+pub mod materials;
+pub mod media;
+pub mod noise;
 pub mod ray_resolvers;
 pub mod renderers;
 mod scene;
+pub mod spectrum;
+pub mod textures;
 pub mod utilities;
 
 use crate::renderers::renderer::Renderer;
+use differentials::RayDifferential;
 use exr::prelude::*;
+use film::{
+    filter::{Filter, FilterType},
+    Film, FilmTile,
+};
 use image::ImageBuffer;
 use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use rand_distr::Uniform;
+use rand_distr::{Distribution, Uniform};
 use ray_resolvers::ray_resolver::RayResolver;
 use rayon::prelude::*;
 use renderers::{albedo, basic_renderer, normal, path_tracer};
-use scene::get_resolver;
+use scene::{get_basic_lights, get_scene};
 use std::{
     f32::consts::{FRAC_PI_2, PI},
+    sync::Mutex,
     usize,
 };
 use utilities::{SceneData, Vector3};
@@ -34,9 +62,12 @@ const HEIGHT_F: f32 = HEIGHT as f32 / 2f32;
 
 const ASPECT_RATIO: f32 = WIDTH_F / HEIGHT_F;
 
-const PIXELS: u32 = WIDTH * HEIGHT;
 const FOV: f32 = 1.2;
 
+const FILTER: Filter = Filter::new(FilterType::MitchellNetravali(1.0 / 3.0, 1.0 / 3.0), 2.0);
+//Single sample renderers only cover the pixel they sample
+const SINGLE_SAMPLE_FILTER: Filter = Filter::new(FilterType::Box, 0.5);
+
 #[allow(dead_code)]
 enum Renderers {
     BasicRenderer,
@@ -65,16 +96,41 @@ enum CameraTypes {
 const CAMERA_TYPE: CameraTypes = CameraTypes::Normal;
 
 fn main() {
-    let resolver = get_resolver();
+    let loaded = get_scene();
+    let (resolver, emitters) = (loaded.resolver, loaded.emitters);
+    //Scene files with a camera are seen through it
+    let (camera_position, camera_target, fov) = match loaded.camera {
+        Some(camera) => (
+            camera.position,
+            camera.target,
+            camera
+                .yfov
+                .map(|yfov| 1f32 / (yfov * 0.5).tan())
+                .unwrap_or(FOV),
+        ),
+        None => (
+            Vector3::new(0.0, 4.0, -5.0),
+            Vector3::new(0f32, 2.0f32, 0f32),
+            FOV,
+        ),
+    };
     let scene = utilities::SceneData {
-        camera_position: Vector3::new(0.0, 4.0, -5.0),
-        camera_target: Vector3::new(0f32, 2.0f32, 0f32),
+        camera_position,
+        camera_target,
+        fov,
         fog_amount: 50.0,
         fog: false,
+        fog_albedo: Vector3::from_single(1f32),
+        fog_anisotropy: 0f32,
+        fog_falloff: 0f32,
+        fog_height: 0f32,
     };
     match RENDERER {
         Renderers::BasicRenderer => {
-            let renderer = basic_renderer::BasicRenderer { resolver };
+            let renderer = basic_renderer::BasicRenderer {
+                resolver,
+                lights: get_basic_lights(&loaded.lights),
+            };
             save_render(&renderer, &scene, FILE_NAME);
         }
         Renderers::PathTracer => {
@@ -110,6 +166,14 @@ fn main() {
                 dof: false,
                 skybox_size: s,
                 skybox: pixels,
+                clamp_direct: None,
+                clamp_indirect: None,
+                regularization: None,
+                spectral: false,
+                volume_steps: 256,
+                volumes: Vec::new(),
+                lights: loaded.lights,
+                emitters,
             };
             save_render(&renderer, &scene, FILE_NAME);
         }
@@ -131,27 +195,38 @@ where
     let style = ProgressStyle::default_bar()
         .template("{prefix}[{wide_bar}] {percent}%")
         .progress_chars("=> ");
-    let bar = ProgressBar::new(PIXELS as u64);
-    bar.set_draw_delta((PIXELS / 100) as u64);
+    let bar = ProgressBar::new(HEIGHT as u64);
+    bar.set_draw_delta((HEIGHT / 100) as u64);
     bar.set_style(style);
     bar.set_prefix("Rendering... ");
+    let filter = if renderer.samples() > 1 {
+        FILTER
+    } else {
+        SINGLE_SAMPLE_FILTER
+    };
+    let film = Mutex::new(Film::new(WIDTH, HEIGHT));
+    (0..HEIGHT)
+        .into_par_iter()
+        .progress_with(bar)
+        .for_each(|y| {
+            let mut tile = FilmTile::for_row(WIDTH, HEIGHT, y, &filter);
+            render_row(renderer, scene, &mut tile, y);
+            film.lock().unwrap().merge_tile(tile);
+        });
+    let pixels = film.into_inner().unwrap().resolve();
     match OUTPUT_FORMAT {
         OutputFormats::PNG => {
-            let pixels: Vec<u8> = (0..PIXELS)
+            let pixels: Vec<u8> = pixels
                 .into_par_iter()
-                .progress_with(bar)
-                .map(|i| {
-                    let x = i % WIDTH;
-                    let y = i / WIDTH;
+                .map(|color| {
                     //TODO: better toneing
                     let color = if T::needs_toneing() {
-                        let color = render_pixel(renderer, scene, x, y);
                         let color_g = color.pow(1f32 / 2.2f32);
                         let lum = color_g.x * 0.2126 + color_g.y * 0.7152 + color_g.z * 0.0722;
                         let color = color.multiply(2.0 / (lum + 1.0));
                         color.pow(1f32 / 2.2f32)
                     } else {
-                        render_pixel(renderer, scene, x, y)
+                        color
                     };
                     color.to_color_array().to_vec()
                 })
@@ -163,16 +238,6 @@ where
             image.save(file_name).unwrap();
         }
         OutputFormats::OPENEXR => {
-            let pixels: Vec<Vector3> = (0..PIXELS)
-                .into_par_iter()
-                .progress_with(bar)
-                .map(|i| {
-                    let x = i % WIDTH;
-                    let y = i / WIDTH;
-                    let color = render_pixel(renderer, scene, x, y);
-                    color
-                })
-                .collect();
             println!("\n\nWriting to {}", file_name);
             let layer = Layer::new(
                 (WIDTH as usize, HEIGHT as usize),
@@ -190,26 +255,64 @@ where
     }
 }
 
-fn render_pixel<T: Renderer<J>, J: RayResolver>(
+fn render_row<T: Renderer<J>, J: RayResolver>(
     renderer: &T,
     scene: &SceneData,
-    x: u32,
+    tile: &mut FilmTile,
     y: u32,
+) {
+    let samples = renderer.samples();
+    //Samples are spread over a pixel, so each covers a smaller footprint
+    let scale = (1f32 / (samples as f32).sqrt()).max(0.125);
+    let mut rng = rand::thread_rng();
+    let jitter = Uniform::new(0f32, 1f32);
+    for x in 0..WIDTH {
+        for _ in 0..samples {
+            //Single sample renderers always sample the pixel center
+            let (dx, dy) = if samples > 1 {
+                (jitter.sample(&mut rng), jitter.sample(&mut rng))
+            } else {
+                (0.5, 0.5)
+            };
+            let raster_x = x as f32 + dx;
+            let raster_y = y as f32 + dy;
+            let color = render_pixel(renderer, scene, raster_x, raster_y, scale);
+            tile.add_sample(raster_x, raster_y, color);
+        }
+    }
+}
+
+fn render_pixel<T: Renderer<J>, J: RayResolver>(
+    renderer: &T,
+    scene: &SceneData,
+    x: f32,
+    y: f32,
+    scale: f32,
 ) -> Vector3 {
+    let start = scene.camera_position;
+    let ray_dir = camera_direction(scene, x, y);
+    let differential = RayDifferential::new(
+        ray_dir,
+        camera_direction(scene, x + 1f32, y),
+        camera_direction(scene, x, y + 1f32),
+    )
+    .scaled(scale);
+    renderer.render(start, ray_dir, differential, scene.clone())
+}
+
+//Direction of the camera ray through a point on the raster
+fn camera_direction(scene: &SceneData, x: f32, y: f32) -> Vector3 {
     match CAMERA_TYPE {
         CameraTypes::Normal => {
-            let start = scene.camera_position;
-            let _x: f32 = (x as f32 / WIDTH_F - 1f32) * ASPECT_RATIO;
-            let _y: f32 = -(y as f32 / HEIGHT_F - 1f32);
-            let ray_dir = scene
+            let _x: f32 = (x / WIDTH_F - 1f32) * ASPECT_RATIO;
+            let _y: f32 = -(y / HEIGHT_F - 1f32);
+            scene
                 .get_look_matrix()
-                .multiply(utilities::Vector3::new(_x, _y, FOV).normalized());
-            renderer.render(start, ray_dir, scene.clone(), WIDTH, HEIGHT)
+                .multiply(utilities::Vector3::new(_x, _y, scene.fov).normalized())
         }
         CameraTypes::Equirectangular => {
-            let start = scene.camera_position;
-            let clip_x: f32 = x as f32 / WIDTH_F - 1f32;
-            let clip_y: f32 = -(y as f32 / HEIGHT_F - 1f32);
+            let clip_x: f32 = x / WIDTH_F - 1f32;
+            let clip_y: f32 = -(y / HEIGHT_F - 1f32);
 
             let latitude = clip_y * FRAC_PI_2;
             let longitude = clip_x * PI;
@@ -218,8 +321,7 @@ fn render_pixel<T: Renderer<J>, J: RayResolver>(
             let _z = longitude.cos() * t;
             let _x = longitude.sin() * t;
 
-            let ray_dir = Vector3::new(_x, _y, _z);
-            renderer.render(start, ray_dir, scene.clone(), WIDTH, HEIGHT)
+            Vector3::new(_x, _y, _z)
         }
     }
 }
@@ -0,0 +1,157 @@
+use crate::utilities::Vector3;
+
+use super::fresnel;
+
+//Wavelengths in nanometers at which measured tables are sampled for RGB rendering
+const RGB_WAVELENGTHS: (f32, f32, f32) = (630f32, 532f32, 465f32);
+
+//Measured complex IOR as (wavelength in nanometers, eta, k), sorted by wavelength
+type Table = &'static [(f32, f32, f32)];
+
+const GOLD: Table = &[
+    (400f32, 1.658, 1.956),
+    (450f32, 1.503, 1.878),
+    (500f32, 0.970, 1.870),
+    (550f32, 0.430, 2.455),
+    (600f32, 0.250, 2.970),
+    (650f32, 0.160, 3.500),
+    (700f32, 0.160, 3.950),
+];
+
+const SILVER: Table = &[
+    (400f32, 0.050, 2.070),
+    (450f32, 0.040, 2.660),
+    (500f32, 0.050, 3.090),
+    (550f32, 0.060, 3.590),
+    (600f32, 0.060, 4.020),
+    (650f32, 0.050, 4.430),
+    (700f32, 0.040, 4.840),
+];
+
+const COPPER: Table = &[
+    (400f32, 1.176, 2.130),
+    (450f32, 1.240, 2.400),
+    (500f32, 1.120, 2.600),
+    (550f32, 1.000, 2.580),
+    (600f32, 0.250, 3.410),
+    (650f32, 0.214, 3.670),
+    (700f32, 0.213, 4.205),
+];
+
+const ALUMINIUM: Table = &[
+    (400f32, 0.490, 4.860),
+    (450f32, 0.620, 5.470),
+    (500f32, 0.770, 6.080),
+    (550f32, 0.960, 6.690),
+    (600f32, 1.200, 7.260),
+    (650f32, 1.470, 7.790),
+    (700f32, 1.830, 8.310),
+];
+
+const CHROME: Table = &[
+    (400f32, 1.870, 2.870),
+    (450f32, 2.130, 3.070),
+    (500f32, 2.750, 3.330),
+    (550f32, 3.180, 3.330),
+    (600f32, 3.170, 3.300),
+    (650f32, 3.100, 3.330),
+    (700f32, 3.090, 3.360),
+];
+
+//Metal described by its complex index of refraction eta + ik per channel
+#[derive(Clone, Copy)]
+pub struct Conductor {
+    pub eta: Vector3,
+    pub k: Vector3,
+    //Measured data eta and k were taken from, if any
+    table: Option<Table>,
+}
+
+impl Conductor {
+    pub fn new(eta: Vector3, k: Vector3) -> Self {
+        Self {
+            eta,
+            k,
+            table: None,
+        }
+    }
+
+    fn measured(table: Table) -> Self {
+        let (r, g, b) = RGB_WAVELENGTHS;
+        Self {
+            table: Some(table),
+            ..Self::new(Vector3::zero(), Vector3::zero())
+        }
+        .to_spectral(Vector3::new(r, g, b))
+    }
+
+    pub fn gold() -> Self {
+        Self::measured(GOLD)
+    }
+
+    pub fn silver() -> Self {
+        Self::measured(SILVER)
+    }
+
+    pub fn copper() -> Self {
+        Self::measured(COPPER)
+    }
+
+    pub fn aluminium() -> Self {
+        Self::measured(ALUMINIUM)
+    }
+
+    pub fn chrome() -> Self {
+        Self::measured(CHROME)
+    }
+
+    //Conductor as seen by the given wavelengths
+    pub fn to_spectral(&self, wavelengths: Vector3) -> Self {
+        let (eta, k) = match self.table {
+            Some(table) => sample(table, wavelengths),
+            //eta and k of each channel are taken as measured at that channel's wavelength.
+            //Reflectance spectra don't apply to them, as they can be far above one
+            None => {
+                let (r, g, b) = RGB_WAVELENGTHS;
+                let (eta, k) = (self.eta, self.k);
+                let table = [(b, eta.z, k.z), (g, eta.y, k.y), (r, eta.x, k.x)];
+                sample(&table, wavelengths)
+            }
+        };
+        Self {
+            eta,
+            k,
+            table: self.table,
+        }
+    }
+
+    pub fn reflectance(&self, cos: f32) -> Vector3 {
+        fresnel::conductor(cos, self.eta, self.k)
+    }
+}
+
+//Linearly interpolates a table, clamping outside of its range
+fn lookup(table: &[(f32, f32, f32)], wavelength: f32) -> (f32, f32) {
+    let i = table
+        .iter()
+        .position(|(l, _, _)| *l >= wavelength)
+        .unwrap_or(table.len() - 1);
+    if i == 0 || table[i].0 <= wavelength {
+        return (table[i].1, table[i].2);
+    }
+    let (l0, eta0, k0) = table[i - 1];
+    let (l1, eta1, k1) = table[i];
+    let t = (wavelength - l0) / (l1 - l0);
+    (eta0 + (eta1 - eta0) * t, k0 + (k1 - k0) * t)
+}
+
+//eta and k at each of the wavelengths
+fn sample(table: &[(f32, f32, f32)], wavelengths: Vector3) -> (Vector3, Vector3) {
+    let (eta_x, k_x) = lookup(table, wavelengths.x);
+    let (eta_y, k_y) = lookup(table, wavelengths.y);
+    let (eta_z, k_z) = lookup(table, wavelengths.z);
+    (
+        Vector3::new(eta_x, eta_y, eta_z),
+        Vector3::new(k_x, k_y, k_z),
+    )
+}
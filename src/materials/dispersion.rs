@@ -0,0 +1,40 @@
+//Wavelength dependent index of refraction, only used when rendering spectrally
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    //n = A + B / λ², λ in micrometers
+    Cauchy(f32, f32),
+    //n² = 1 + Σ Bᵢλ² / (λ² - Cᵢ), λ in micrometers
+    Sellmeier([f32; 3], [f32; 3]),
+}
+
+impl Dispersion {
+    pub fn bk7() -> Self {
+        Dispersion::Sellmeier(
+            [1.039_612, 0.231_792_34, 1.010_469_5],
+            [0.006_000_699, 0.020_017_914, 103.560_65],
+        )
+    }
+
+    pub fn fused_silica() -> Self {
+        Dispersion::Sellmeier(
+            [0.696_166_3, 0.407_942_6, 0.897_479_4],
+            [0.004_679_148, 0.013_512_063, 97.934_0],
+        )
+    }
+
+    pub fn diamond() -> Self {
+        Dispersion::Sellmeier([4.3356, 0.3306, 0f32], [0.011_236, 0.030_625, 0f32])
+    }
+
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l = wavelength / 1000f32;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy(a, b) => a + b / l2,
+            Dispersion::Sellmeier(b, c) => {
+                let n2 = 1f32 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>();
+                n2.sqrt()
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::utilities::Vector3;
+
+pub fn schlick(f0: Vector3, cos: f32) -> Vector3 {
+    let m = (1f32 - cos).clamp(0f32, 1f32).powi(5);
+    f0.add(Vector3::from_single(1f32).subtract(f0).multiply(m))
+}
+
+//Unpolarized reflectance of a dielectric interface.
+//`eta` is the IOR of the transmitted side divided by the IOR of the incident side.
+pub fn dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = cos_i.clamp(0f32, 1f32);
+    let sin2_t = (1f32 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1f32 {
+        //Total internal reflection
+        return 1f32;
+    }
+    let cos_t = (1f32 - sin2_t).sqrt();
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) * 0.5
+}
+
+//Unpolarized reflectance of a conductor with complex IOR `eta` + i`k`, relative to the
+//incident side
+pub fn conductor(cos_i: f32, eta: Vector3, k: Vector3) -> Vector3 {
+    let cos_i = cos_i.clamp(0f32, 1f32);
+    Vector3::new(
+        conductor_channel(cos_i, eta.x, k.x),
+        conductor_channel(cos_i, eta.y, k.y),
+        conductor_channel(cos_i, eta.z, k.z),
+    )
+}
+
+fn conductor_channel(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1f32 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_b2 = (t0 * t0 + 4f32 * eta * eta * k * k).sqrt();
+    let a = ((a2_b2 + t0) * 0.5).max(0f32).sqrt();
+    let t1 = a2_b2 + cos2;
+    let t2 = 2f32 * cos_i * a;
+    let r_perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let r_parallel = r_perpendicular * (t3 - t4) / (t3 + t4);
+    (r_parallel + r_perpendicular) * 0.5
+}
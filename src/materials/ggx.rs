@@ -0,0 +1,257 @@
+use std::f32::consts::PI;
+
+use crate::utilities::{Matrix3, Vector3};
+
+use super::{fresnel, BsdfSample};
+
+//Trowbridge-Reitz (GGX) microfacet lobes. Directions point away from the surface
+//and `eta` is the IOR of the transmitted side divided by the IOR of the side of `wo`.
+
+pub fn roughness_to_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-3)
+}
+
+pub fn distribution(h: Vector3, alpha: f32) -> f32 {
+    if h.z <= 0f32 {
+        return 0f32;
+    }
+    let a2 = alpha * alpha;
+    let d = h.z * h.z * (a2 - 1f32) + 1f32;
+    a2 / (PI * d * d)
+}
+
+fn lambda(w: Vector3, alpha: f32) -> f32 {
+    let cos2 = w.z * w.z;
+    if cos2 == 0f32 {
+        return f32::INFINITY;
+    }
+    let tan2 = (1f32 - cos2).max(0f32) / cos2;
+    ((1f32 + alpha * alpha * tan2).sqrt() - 1f32) * 0.5
+}
+
+pub fn masking(w: Vector3, alpha: f32) -> f32 {
+    1f32 / (1f32 + lambda(w, alpha))
+}
+
+//Height correlated masking-shadowing
+pub fn masking_shadowing(wo: Vector3, wi: Vector3, alpha: f32) -> f32 {
+    1f32 / (1f32 + lambda(wo, alpha) + lambda(wi, alpha))
+}
+
+//Samples a microfacet normal visible from `wo` (Heitz 2018)
+pub fn sample_visible_normal(wo: Vector3, alpha: f32) -> Vector3 {
+    let vh = Vector3::new(alpha * wo.x, alpha * wo.y, wo.z).normalized();
+    let len2 = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len2 > 0f32 {
+        Vector3::new(-vh.y, vh.x, 0f32).multiply(1f32 / len2.sqrt())
+    } else {
+        Vector3::new(1f32, 0f32, 0f32)
+    };
+    let t2 = vh.cross(t1);
+    let r = rand::random::<f32>().sqrt();
+    let phi = 2f32 * PI * rand::random::<f32>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1f32 + vh.z);
+    let p2 = (1f32 - s) * (1f32 - p1 * p1).max(0f32).sqrt() + s * r * phi.sin();
+    let nh = t1
+        .multiply(p1)
+        .add(t2.multiply(p2))
+        .add(vh.multiply((1f32 - p1 * p1 - p2 * p2).max(0f32).sqrt()));
+    Vector3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1e-6)).normalized()
+}
+
+pub fn visible_normal_pdf(wo: Vector3, h: Vector3, alpha: f32) -> f32 {
+    masking(wo, alpha) * wo.dot(h).max(0f32) * distribution(h, alpha) / wo.z
+}
+
+fn reflect(wo: Vector3, h: Vector3) -> Vector3 {
+    h.multiply(2f32 * wo.dot(h)).subtract(wo)
+}
+
+fn refract(wo: Vector3, h: Vector3, eta: f32) -> Option<Vector3> {
+    let cos_o = wo.dot(h);
+    let sin2_t = (1f32 - cos_o * cos_o).max(0f32) / (eta * eta);
+    if sin2_t >= 1f32 {
+        return None;
+    }
+    let cos_t = (1f32 - sin2_t).sqrt();
+    Some(
+        wo.multiply(-1f32 / eta)
+            .add(h.multiply(cos_o / eta - cos_t)),
+    )
+}
+
+//Shading frame with `wo` in the upper hemisphere
+fn local_frame(wo: Vector3, normal: Vector3) -> Matrix3 {
+    if wo.dot(normal) < 0f32 {
+        Matrix3::from_normal(normal.multiply(-1f32))
+    } else {
+        Matrix3::from_normal(normal)
+    }
+}
+
+//Generalized half vector of a refraction, oriented towards `wo`
+fn refraction_half_vector(wo: Vector3, wi: Vector3, eta: f32) -> Vector3 {
+    let h = wo.add(wi.multiply(eta)).normalized();
+    if h.z < 0f32 {
+        h.multiply(-1f32)
+    } else {
+        h
+    }
+}
+
+//`fresnel` gives the reflectance for the cosine between `wo` and the microfacet normal
+pub fn sample_conductor(
+    wo: Vector3,
+    normal: Vector3,
+    fresnel: impl Fn(f32) -> Vector3,
+    roughness: f32,
+) -> Option<BsdfSample> {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let alpha = roughness_to_alpha(roughness);
+    let h = sample_visible_normal(wo, alpha);
+    let wi = reflect(wo, h);
+    if wi.z <= 0f32 {
+        return None;
+    }
+    let f = fresnel(wo.dot(h));
+    Some(BsdfSample {
+        dir: frame.multiply(wi),
+        weight: f.multiply(masking_shadowing(wo, wi, alpha) / masking(wo, alpha)),
+        pdf: visible_normal_pdf(wo, h, alpha) / (4f32 * wo.dot(h)),
+        specular: false,
+    })
+}
+
+//BSDF * cosine
+pub fn eval_conductor(
+    wo: Vector3,
+    wi: Vector3,
+    normal: Vector3,
+    fresnel: impl Fn(f32) -> Vector3,
+    roughness: f32,
+) -> Vector3 {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let wi = frame.transpose_multiply(wi);
+    if wi.z <= 0f32 || wo.z <= 0f32 {
+        return Vector3::zero();
+    }
+    let alpha = roughness_to_alpha(roughness);
+    let h = wo.add(wi).normalized();
+    let f = fresnel(wo.dot(h));
+    f.multiply(distribution(h, alpha) * masking_shadowing(wo, wi, alpha) / (4f32 * wo.z))
+}
+
+pub fn pdf_conductor(wo: Vector3, wi: Vector3, normal: Vector3, roughness: f32) -> f32 {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let wi = frame.transpose_multiply(wi);
+    if wi.z <= 0f32 || wo.z <= 0f32 {
+        return 0f32;
+    }
+    let alpha = roughness_to_alpha(roughness);
+    let h = wo.add(wi).normalized();
+    visible_normal_pdf(wo, h, alpha) / (4f32 * wo.dot(h))
+}
+
+pub fn sample_dielectric(
+    wo: Vector3,
+    normal: Vector3,
+    color: Vector3,
+    eta: f32,
+    roughness: f32,
+) -> Option<BsdfSample> {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let alpha = roughness_to_alpha(roughness);
+    let h = sample_visible_normal(wo, alpha);
+    let cos_o = wo.dot(h);
+    let r = fresnel::dielectric(cos_o, eta);
+    let h_pdf = visible_normal_pdf(wo, h, alpha);
+    if rand::random::<f32>() < r {
+        let wi = reflect(wo, h);
+        if wi.z <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            dir: frame.multiply(wi),
+            weight: Vector3::from_single(masking_shadowing(wo, wi, alpha) / masking(wo, alpha)),
+            pdf: h_pdf / (4f32 * cos_o) * r,
+            specular: false,
+        })
+    } else {
+        let wi = refract(wo, h, eta)?;
+        if wi.z >= 0f32 {
+            return None;
+        }
+        let denom = wi.dot(h) + cos_o / eta;
+        let dh_dwi = wi.dot(h).abs() / (denom * denom);
+        let g = masking_shadowing(wo, wi, alpha) / masking(wo, alpha);
+        Some(BsdfSample {
+            dir: frame.multiply(wi),
+            weight: color.multiply(g / (eta * eta)),
+            pdf: h_pdf * dh_dwi * (1f32 - r),
+            specular: false,
+        })
+    }
+}
+
+//BSDF * cosine
+pub fn eval_dielectric(
+    wo: Vector3,
+    wi: Vector3,
+    normal: Vector3,
+    color: Vector3,
+    eta: f32,
+    roughness: f32,
+) -> Vector3 {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let wi = frame.transpose_multiply(wi);
+    if wo.z <= 0f32 || wi.z == 0f32 {
+        return Vector3::zero();
+    }
+    let alpha = roughness_to_alpha(roughness);
+    if wi.z > 0f32 {
+        let h = wo.add(wi).normalized();
+        let r = fresnel::dielectric(wo.dot(h), eta);
+        let f = r * distribution(h, alpha) * masking_shadowing(wo, wi, alpha) / (4f32 * wo.z);
+        return Vector3::from_single(f);
+    }
+    let h = refraction_half_vector(wo, wi, eta);
+    if wo.dot(h) <= 0f32 || wi.dot(h) >= 0f32 {
+        return Vector3::zero();
+    }
+    let t = 1f32 - fresnel::dielectric(wo.dot(h), eta);
+    let denom = wi.dot(h) + wo.dot(h) / eta;
+    let f = t
+        * distribution(h, alpha)
+        * masking_shadowing(wo, wi, alpha)
+        * (wi.dot(h) * wo.dot(h)).abs()
+        / (wo.z * denom * denom * eta * eta);
+    color.multiply(f)
+}
+
+pub fn pdf_dielectric(wo: Vector3, wi: Vector3, normal: Vector3, eta: f32, roughness: f32) -> f32 {
+    let frame = local_frame(wo, normal);
+    let wo = frame.transpose_multiply(wo);
+    let wi = frame.transpose_multiply(wi);
+    if wo.z <= 0f32 || wi.z == 0f32 {
+        return 0f32;
+    }
+    let alpha = roughness_to_alpha(roughness);
+    if wi.z > 0f32 {
+        let h = wo.add(wi).normalized();
+        let r = fresnel::dielectric(wo.dot(h), eta);
+        return visible_normal_pdf(wo, h, alpha) / (4f32 * wo.dot(h)) * r;
+    }
+    let h = refraction_half_vector(wo, wi, eta);
+    if wo.dot(h) <= 0f32 || wi.dot(h) >= 0f32 {
+        return 0f32;
+    }
+    let t = 1f32 - fresnel::dielectric(wo.dot(h), eta);
+    let denom = wi.dot(h) + wo.dot(h) / eta;
+    visible_normal_pdf(wo, h, alpha) * wi.dot(h).abs() / (denom * denom) * t
+}
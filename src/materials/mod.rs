@@ -0,0 +1,27 @@
+use crate::utilities::Vector3;
+
+pub mod conductor;
+pub mod dispersion;
+pub mod fresnel;
+pub mod ggx;
+pub mod principled;
+
+pub struct BsdfSample {
+    pub dir: Vector3,
+    //BSDF * cosine / pdf
+    pub weight: Vector3,
+    pub pdf: f32,
+    //Perfectly specular bounces have no meaningful pdf
+    pub specular: bool,
+}
+
+impl BsdfSample {
+    pub fn specular(dir: Vector3, weight: Vector3) -> Self {
+        Self {
+            dir,
+            weight,
+            pdf: 0f32,
+            specular: true,
+        }
+    }
+}
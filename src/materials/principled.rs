@@ -0,0 +1,524 @@
+use std::{f32::consts::PI, sync::Arc};
+
+use crate::{
+    media::Medium,
+    spectrum,
+    textures::{procedural::Texture, Footprint, ImageTexture, MaterialTextures},
+    utilities::Vector3,
+};
+
+use super::{conductor::Conductor, dispersion::Dispersion, fresnel, ggx, BsdfSample};
+
+const CLEARCOAT_ROUGHNESS: f32 = 0.1;
+const CLEARCOAT_F0: f32 = 0.04;
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const METAL: usize = 2;
+const TRANSMISSION: usize = 3;
+const CLEARCOAT: usize = 4;
+const SUBSURFACE: usize = 5;
+const LOBES: usize = 6;
+
+#[derive(Clone)]
+pub struct Material {
+    pub base_color: Vector3,
+    pub metallic: f32,
+    //Complex IOR of the metal, tinting it by `base_color` at normal incidence when absent
+    pub conductor: Option<Conductor>,
+    pub roughness: f32,
+    //Dielectric reflectivity, 0.5 is 4% at normal incidence
+    pub specular: f32,
+    pub transmission: f32,
+    pub ior: f32,
+    //Replaces `ior` when rendering spectrally
+    pub dispersion: Option<Dispersion>,
+    pub clearcoat: f32,
+    pub sheen: f32,
+    //Fraction of the diffuse light that enters the surface and scatters below it
+    pub subsurface: f32,
+    //Mean free path of each channel below the surface
+    pub subsurface_radius: Vector3,
+    pub emission: Vector3,
+    pub emission_strength: f32,
+    //Absorption coefficient of the volume enclosed by a transmissive surface
+    pub absorption: Vector3,
+    pub scattering: Vector3,
+    //Priority of the enclosed volume where it overlaps others
+    pub priority: u32,
+    //Whether rays hit the back faces of meshes with this material
+    pub double_sided: bool,
+    //Looked up at every hit by `textured`
+    pub textures: Option<Arc<MaterialTextures>>,
+    //Color interpolated from the vertices of the hit mesh, replacing `base_color`
+    pub vertex_color: Option<Vector3>,
+    //Multiply vertex colors with the material's color instead
+    pub multiply_vertex_color: bool,
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self {
+            base_color: Vector3::from_single(0.8),
+            metallic: 0f32,
+            conductor: None,
+            roughness: 0.5,
+            specular: 0.5,
+            transmission: 0f32,
+            ior: 1.45,
+            dispersion: None,
+            clearcoat: 0f32,
+            sheen: 0f32,
+            subsurface: 0f32,
+            subsurface_radius: Vector3::new(1f32, 0.2, 0.1),
+            emission: Vector3::zero(),
+            emission_strength: 0f32,
+            absorption: Vector3::zero(),
+            scattering: Vector3::zero(),
+            priority: 0,
+            double_sided: false,
+            textures: None,
+            vertex_color: None,
+            multiply_vertex_color: false,
+        }
+    }
+}
+
+impl Material {
+    pub fn diffuse(color: Vector3) -> Self {
+        Self {
+            base_color: color,
+            roughness: 1f32,
+            specular: 0f32,
+            ..Default::default()
+        }
+    }
+
+    pub fn metal(color: Vector3, roughness: f32) -> Self {
+        Self {
+            base_color: color,
+            metallic: 1f32,
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    pub fn conductor(conductor: Conductor, roughness: f32) -> Self {
+        Self {
+            base_color: conductor.reflectance(1f32),
+            metallic: 1f32,
+            conductor: Some(conductor),
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    //Replaces both `MaterialType::Glass` and `MaterialType::Lens`. Lens sent every ray straight
+    //down the surface normal regardless of where it came from, which no physical surface does,
+    //so it was removed rather than mapped; glass with a high `ior` is the closest match
+    pub fn glass(color: Vector3, ior: f32, roughness: f32) -> Self {
+        Self {
+            base_color: color,
+            roughness,
+            transmission: 1f32,
+            ior,
+            ..Default::default()
+        }
+    }
+
+    pub fn subsurface(color: Vector3, radius: Vector3) -> Self {
+        Self {
+            base_color: color,
+            subsurface: 1f32,
+            subsurface_radius: radius,
+            ..Default::default()
+        }
+    }
+
+    pub fn emissive(color: Vector3, strength: f32) -> Self {
+        Self {
+            base_color: Vector3::zero(),
+            specular: 0f32,
+            emission: color,
+            emission_strength: strength,
+            ..Default::default()
+        }
+    }
+
+    //Material as seen by the given wavelengths, with every color converted to spectral values
+    pub fn to_spectral(&self, wavelengths: Vector3) -> Self {
+        let ior = match self.dispersion {
+            Some(dispersion) => dispersion.ior(wavelengths.x),
+            None => self.ior,
+        };
+        Self {
+            base_color: spectrum::rgb_to_spectrum(self.base_color, wavelengths),
+            emission: spectrum::rgb_to_spectrum(self.emission, wavelengths),
+            absorption: spectrum::rgb_to_spectrum(self.absorption, wavelengths),
+            scattering: spectrum::rgb_to_spectrum(self.scattering, wavelengths),
+            subsurface_radius: spectrum::rgb_to_spectrum(self.subsurface_radius, wavelengths),
+            ior,
+            conductor: self.conductor.map(|c| c.to_spectral(wavelengths)),
+            ..self.clone()
+        }
+    }
+
+    //Volume enclosed by the surface, if light can enter it
+    pub fn interior(&self) -> Option<Medium> {
+        if self.subsurface > 0f32 {
+            //Random walk volume whose multiple scattering reproduces `base_color`
+            //on the surface (van de Hulst)
+            let a = self
+                .base_color
+                .max(Vector3::zero())
+                .min(Vector3::from_single(1f32));
+            let albedo = Vector3::new(
+                subsurface_albedo(a.x),
+                subsurface_albedo(a.y),
+                subsurface_albedo(a.z),
+            );
+            let extinction = self
+                .subsurface_radius
+                .max(Vector3::from_single(1e-4))
+                .reciprocal();
+            let scattering = extinction.comp_multiply(albedo);
+            return Some(Medium {
+                ior: self.ior,
+                absorption: extinction.subtract(scattering),
+                scattering,
+                priority: self.priority,
+            });
+        }
+        if self.transmission <= 0f32 {
+            return None;
+        }
+        Some(Medium {
+            ior: self.ior,
+            absorption: self.absorption,
+            scattering: self.scattering,
+            priority: self.priority,
+        })
+    }
+
+    //Surfaces enclosing a volume are always double sided, so that paths can leave it
+    pub fn is_double_sided(&self) -> bool {
+        self.double_sided || self.transmission > 0f32 || self.subsurface > 0f32
+    }
+
+    pub fn with_textures(self, textures: MaterialTextures) -> Self {
+        Self {
+            textures: Some(Arc::new(textures)),
+            ..self
+        }
+    }
+
+    //Material colored by the mesh's vertices at a hit
+    pub fn with_vertex_color(self, color: Vector3) -> Self {
+        let material = Self {
+            vertex_color: Some(color),
+            ..self
+        };
+        Self {
+            base_color: material.tinted(material.base_color),
+            ..material
+        }
+    }
+
+    //Combines a color of the material with the vertex color
+    fn tinted(&self, color: Vector3) -> Vector3 {
+        match self.vertex_color {
+            Some(vertex_color) if self.multiply_vertex_color => color.comp_multiply(vertex_color),
+            Some(vertex_color) => vertex_color,
+            None => color,
+        }
+    }
+
+    //Material with its textures looked up at a position with the given texture coordinates
+    pub fn textured(&self, position: Vector3, uv: [f32; 2]) -> Self {
+        self.filtered(position, uv, &Footprint::point())
+    }
+
+    //Material with its textures averaged over the footprint of a pixel
+    pub fn filtered(&self, position: Vector3, uv: [f32; 2], footprint: &Footprint) -> Self {
+        let mut material = self.clone();
+        let textures = match &self.textures {
+            Some(textures) => textures,
+            None => return material,
+        };
+        let evaluate = |texture: &Texture| texture.evaluate(position, uv, footprint);
+        if let Some(texture) = &textures.base_color {
+            material.base_color = self.tinted(evaluate(texture));
+        }
+        if let Some(texture) = &textures.specular {
+            material.specular = evaluate(texture).average();
+        }
+        if let Some(texture) = &textures.roughness {
+            material.roughness = evaluate(texture).average();
+        }
+        if let Some(texture) = &textures.metallic {
+            material.metallic = evaluate(texture).average();
+        }
+        if let Some(texture) = &textures.emission {
+            material.emission = evaluate(texture);
+        }
+        material
+    }
+
+    //Emission of a material whose emission color is scaled by an image. It starts out as the
+    //average of the image, so that emitters can be found and sampled by their power
+    pub fn textured_emission(color: Vector3, image: Option<&ImageTexture>) -> Vector3 {
+        match image {
+            Some(image) => color.comp_multiply(image.average()),
+            None => color,
+        }
+    }
+
+    pub fn emitted(&self) -> Vector3 {
+        self.emission.multiply(self.emission_strength)
+    }
+
+    //Copy of the material with its lobes widened to at least the given roughness
+    pub fn regularized(&self, roughness: f32) -> Self {
+        Self {
+            roughness: self.roughness.max(roughness),
+            ..self.clone()
+        }
+    }
+
+    fn f0(&self) -> f32 {
+        0.08 * self.specular
+    }
+
+    fn metal_reflectance(&self, cos: f32) -> Vector3 {
+        match &self.conductor {
+            Some(conductor) => conductor.reflectance(cos),
+            None => fresnel::schlick(self.base_color, cos),
+        }
+    }
+
+    fn is_delta(&self, lobe: usize) -> bool {
+        self.roughness == 0f32 && (lobe == SPECULAR || lobe == METAL || lobe == TRANSMISSION)
+    }
+
+    //Probability of picking each lobe when sampling
+    fn lobe_probabilities(&self, cos_o: f32) -> Option<[f32; LOBES]> {
+        let dielectric = (1f32 - self.metallic) * (1f32 - self.transmission);
+        let f = fresnel::schlick(Vector3::from_single(self.f0()), cos_o).x;
+        let clearcoat =
+            0.25 * self.clearcoat * fresnel::schlick(Vector3::from_single(CLEARCOAT_F0), cos_o).x;
+        let mut weights = [0f32; LOBES];
+        weights[DIFFUSE] = dielectric * (1f32 - f) * (1f32 - self.subsurface);
+        weights[SUBSURFACE] = dielectric * (1f32 - f) * self.subsurface;
+        weights[SPECULAR] = dielectric * f;
+        weights[METAL] = self.metallic;
+        weights[TRANSMISSION] = (1f32 - self.metallic) * self.transmission;
+        weights[CLEARCOAT] = clearcoat;
+        let sum: f32 = weights.iter().sum();
+        if sum <= 0f32 {
+            return None;
+        }
+        for w in weights.iter_mut() {
+            *w /= sum;
+        }
+        Some(weights)
+    }
+
+    //BSDF * cosine of a single lobe. `eta` is the IOR ratio across the surface as seen from `wo`
+    fn eval_lobe(
+        &self,
+        lobe: usize,
+        wo: Vector3,
+        wi: Vector3,
+        normal: Vector3,
+        eta: f32,
+    ) -> Vector3 {
+        let dielectric = (1f32 - self.metallic) * (1f32 - self.transmission);
+        match lobe {
+            DIFFUSE => {
+                let cos_i = wi.dot(normal);
+                if cos_i <= 0f32 {
+                    return Vector3::zero();
+                }
+                let h = wo.add(wi).normalized();
+                let sheen = self.sheen * (1f32 - wi.dot(h)).max(0f32).powi(5);
+                self.base_color
+                    .multiply(1f32 - self.f0())
+                    .add_scalar(sheen)
+                    .multiply(dielectric * (1f32 - self.subsurface) * cos_i / PI)
+            }
+            SPECULAR => ggx::eval_conductor(
+                wo,
+                wi,
+                normal,
+                |cos| fresnel::schlick(Vector3::from_single(self.f0()), cos),
+                self.roughness,
+            )
+            .multiply(dielectric),
+            METAL => ggx::eval_conductor(
+                wo,
+                wi,
+                normal,
+                |cos| self.metal_reflectance(cos),
+                self.roughness,
+            )
+            .multiply(self.metallic),
+            TRANSMISSION => {
+                ggx::eval_dielectric(wo, wi, normal, self.base_color, eta, self.roughness)
+                    .multiply((1f32 - self.metallic) * self.transmission)
+            }
+            SUBSURFACE => {
+                //Diffuse transmission into the volume, which does the tinting
+                let cos_i = -wi.dot(normal);
+                if cos_i <= 0f32 {
+                    return Vector3::zero();
+                }
+                Vector3::from_single((1f32 - self.f0()) * dielectric * self.subsurface * cos_i / PI)
+            }
+            _ => ggx::eval_conductor(
+                wo,
+                wi,
+                normal,
+                |cos| fresnel::schlick(Vector3::from_single(CLEARCOAT_F0), cos),
+                CLEARCOAT_ROUGHNESS,
+            )
+            .multiply(0.25 * self.clearcoat),
+        }
+    }
+
+    fn pdf_lobe(&self, lobe: usize, wo: Vector3, wi: Vector3, normal: Vector3, eta: f32) -> f32 {
+        match lobe {
+            DIFFUSE => wi.dot(normal).max(0f32) / PI,
+            SPECULAR | METAL => ggx::pdf_conductor(wo, wi, normal, self.roughness),
+            TRANSMISSION => ggx::pdf_dielectric(wo, wi, normal, eta, self.roughness),
+            SUBSURFACE => (-wi.dot(normal)).max(0f32) / PI,
+            _ => ggx::pdf_conductor(wo, wi, normal, CLEARCOAT_ROUGHNESS),
+        }
+    }
+
+    //BSDF * cosine of every non-specular lobe
+    pub fn eval(&self, wo: Vector3, wi: Vector3, normal: Vector3, eta: f32) -> Vector3 {
+        let normal = facing(normal, wo);
+        (0..LOBES)
+            .filter(|lobe| !self.is_delta(*lobe))
+            .fold(Vector3::zero(), |f, lobe| {
+                f.add(self.eval_lobe(lobe, wo, wi, normal, eta))
+            })
+    }
+
+    pub fn pdf(&self, wo: Vector3, wi: Vector3, normal: Vector3, eta: f32) -> f32 {
+        let normal = facing(normal, wo);
+        let probabilities = match self.lobe_probabilities(wo.dot(normal)) {
+            Some(p) => p,
+            None => return 0f32,
+        };
+        (0..LOBES)
+            .filter(|lobe| !self.is_delta(*lobe))
+            .map(|lobe| probabilities[lobe] * self.pdf_lobe(lobe, wo, wi, normal, eta))
+            .sum()
+    }
+
+    pub fn sample(&self, wo: Vector3, normal: Vector3, eta: f32) -> Option<BsdfSample> {
+        let normal = facing(normal, wo);
+        let cos_o = wo.dot(normal);
+        let probabilities = self.lobe_probabilities(cos_o)?;
+        let lobe = {
+            let mut u = rand::random::<f32>();
+            let mut lobe = 0;
+            while lobe < LOBES - 1 && u >= probabilities[lobe] {
+                u -= probabilities[lobe];
+                lobe += 1;
+            }
+            lobe
+        };
+        let p = probabilities[lobe];
+        let incoming = wo.multiply(-1f32);
+        if self.is_delta(lobe) {
+            let dielectric = (1f32 - self.metallic) * (1f32 - self.transmission);
+            return match lobe {
+                SPECULAR => {
+                    let f = fresnel::schlick(Vector3::from_single(self.f0()), cos_o);
+                    Some(BsdfSample::specular(
+                        incoming.reflect(normal),
+                        f.multiply(dielectric / p),
+                    ))
+                }
+                METAL => {
+                    let f = self.metal_reflectance(cos_o);
+                    Some(BsdfSample::specular(
+                        incoming.reflect(normal),
+                        f.multiply(self.metallic / p),
+                    ))
+                }
+                _ => {
+                    //Choose between reflection and refraction by the Fresnel term,
+                    //which cancels out of the weight
+                    let weight = (1f32 - self.metallic) * self.transmission / p;
+                    let r = fresnel::dielectric(cos_o, eta);
+                    match incoming.refract(normal, 1f32 / eta) {
+                        Some(dir) if rand::random::<f32>() >= r => Some(BsdfSample::specular(
+                            dir,
+                            self.base_color.multiply(weight / (eta * eta)),
+                        )),
+                        _ => Some(BsdfSample::specular(
+                            incoming.reflect(normal),
+                            Vector3::from_single(weight),
+                        )),
+                    }
+                }
+            };
+        }
+        let dir = match lobe {
+            DIFFUSE => Vector3::random_cosine_hemisphere(normal),
+            SPECULAR | METAL => {
+                ggx::sample_conductor(wo, normal, |_| Vector3::zero(), self.roughness)?.dir
+            }
+            TRANSMISSION => {
+                ggx::sample_dielectric(wo, normal, self.base_color, eta, self.roughness)?.dir
+            }
+            SUBSURFACE => Vector3::random_cosine_hemisphere(normal.multiply(-1f32)),
+            _ => ggx::sample_conductor(wo, normal, |_| Vector3::zero(), CLEARCOAT_ROUGHNESS)?.dir,
+        };
+        let pdf = self.pdf(wo, dir, normal, eta);
+        if pdf <= 0f32 {
+            return None;
+        }
+        Some(BsdfSample {
+            dir,
+            weight: self.eval(wo, dir, normal, eta).multiply(1f32 / pdf),
+            pdf,
+            specular: false,
+        })
+    }
+
+    //Leaves the subsurface volume through the surface a random walk reached
+    pub fn sample_subsurface_exit(&self, wo: Vector3, normal: Vector3) -> BsdfSample {
+        let normal = facing(normal, wo).multiply(-1f32);
+        let dir = Vector3::random_cosine_hemisphere(normal);
+        BsdfSample {
+            dir,
+            weight: Vector3::from_single(1f32),
+            pdf: dir.dot(normal).max(0f32) / PI,
+            specular: false,
+        }
+    }
+
+    //BSDF * cosine matching `sample_subsurface_exit`
+    pub fn eval_subsurface_exit(&self, wo: Vector3, wi: Vector3, normal: Vector3) -> Vector3 {
+        let normal = facing(normal, wo).multiply(-1f32);
+        Vector3::from_single(wi.dot(normal).max(0f32) / PI)
+    }
+}
+
+//Single scattering albedo of a random walk volume with the given multiple scattering albedo
+fn subsurface_albedo(a: f32) -> f32 {
+    let t = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    1f32 - t * t
+}
+
+fn facing(normal: Vector3, wo: Vector3) -> Vector3 {
+    if wo.dot(normal) < 0f32 {
+        normal.multiply(-1f32)
+    } else {
+        normal
+    }
+}
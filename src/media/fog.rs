@@ -0,0 +1,49 @@
+use crate::utilities::{SceneData, Vector3};
+
+//Fog filling the scene, whose density falls off exponentially with height
+
+//Below this the falloff along a ray is treated as uniform density
+const UNIFORM_THRESHOLD: f32 = 1e-5;
+
+//Density relative to the nominal density of the fog
+pub fn density(scene: &SceneData, p: Vector3) -> f32 {
+    (-scene.fog_falloff * (p.y - scene.fog_height)).exp()
+}
+
+//Optical depth along a ray up to `distance`
+pub fn optical_depth(scene: &SceneData, start: Vector3, dir: Vector3, distance: f32) -> f32 {
+    if !scene.fog {
+        return 0f32;
+    }
+    let sigma = density(scene, start) / scene.fog_amount;
+    let k = scene.fog_falloff * dir.y;
+    if k.abs() < UNIFORM_THRESHOLD {
+        sigma * distance
+    } else {
+        sigma * (1f32 - (-k * distance).exp()) / k
+    }
+}
+
+pub fn transmittance(scene: &SceneData, start: Vector3, dir: Vector3, distance: f32) -> f32 {
+    (-optical_depth(scene, start, dir, distance)).exp()
+}
+
+//Distance along a ray at which light interacts with the fog, infinite if it never does
+pub fn sample_distance(scene: &SceneData, start: Vector3, dir: Vector3) -> f32 {
+    if !scene.fog {
+        return f32::INFINITY;
+    }
+    let depth = -(1f32 - rand::random::<f32>()).ln();
+    let sigma = density(scene, start) / scene.fog_amount;
+    let k = scene.fog_falloff * dir.y;
+    if k.abs() < UNIFORM_THRESHOLD {
+        return depth / sigma;
+    }
+    //Rays heading up may leave the fog before reaching the sampled depth
+    let x = 1f32 - depth * k / sigma;
+    if x <= 0f32 {
+        f32::INFINITY
+    } else {
+        -x.ln() / k
+    }
+}
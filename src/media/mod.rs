@@ -0,0 +1,197 @@
+use crate::utilities::Vector3;
+
+pub mod fog;
+pub mod phase;
+pub mod volume;
+
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub ior: f32,
+    pub absorption: Vector3,
+    pub scattering: Vector3,
+    //Where volumes overlap, the medium with the highest priority is the one present
+    pub priority: u32,
+}
+
+impl Medium {
+    pub fn vacuum() -> Self {
+        Self {
+            ior: 1f32,
+            absorption: Vector3::zero(),
+            scattering: Vector3::zero(),
+            priority: 0,
+        }
+    }
+
+    //Absorption coefficient that leaves `color` after light travelled `distance`
+    pub fn absorption_from_color(color: Vector3, distance: f32) -> Vector3 {
+        color
+            .max(Vector3::from_single(1e-4))
+            .ln()
+            .multiply(-1f32 / distance)
+    }
+
+    pub fn extinction(&self) -> Vector3 {
+        self.absorption.add(self.scattering)
+    }
+
+    pub fn transmittance(&self, distance: f32) -> Vector3 {
+        //Channels without extinction let everything through, even over infinite distances
+        let channel = |extinction: f32| {
+            if extinction > 0f32 {
+                (-extinction * distance).exp()
+            } else {
+                1f32
+            }
+        };
+        let extinction = self.extinction();
+        Vector3::new(
+            channel(extinction.x),
+            channel(extinction.y),
+            channel(extinction.z),
+        )
+    }
+
+    //Samples where along a segment of the given length light scatters, if it does at all.
+    //The distance is sampled from one color channel, picked in proportion to the
+    //throughput of the path so far to keep long random walks from diverging.
+    pub fn sample_interaction(&self, distance: f32, throughput: Vector3) -> MediumInteraction {
+        let extinction = self.extinction();
+        if self.scattering.largest_component_value() <= 0f32 {
+            return MediumInteraction {
+                scattered: false,
+                distance,
+                weight: self.transmittance(distance),
+            };
+        }
+        let sum = throughput.x + throughput.y + throughput.z;
+        let probabilities = if sum > 0f32 {
+            throughput.multiply(1f32 / sum)
+        } else {
+            Vector3::from_single(1f32 / 3f32)
+        };
+        let u = rand::random::<f32>();
+        let channel = if u < probabilities.x {
+            extinction.x
+        } else if u < probabilities.x + probabilities.y {
+            extinction.y
+        } else {
+            extinction.z
+        };
+        let t = -(1f32 - rand::random::<f32>()).ln() / channel;
+        if t < distance {
+            let transmittance = self.transmittance(t);
+            //Distance pdf of every channel combined
+            let pdf = probabilities.dot(extinction.comp_multiply(transmittance));
+            MediumInteraction {
+                scattered: true,
+                distance: t,
+                weight: self
+                    .scattering
+                    .comp_multiply(transmittance)
+                    .multiply(1f32 / pdf),
+            }
+        } else {
+            let transmittance = self.transmittance(distance);
+            MediumInteraction {
+                scattered: false,
+                distance,
+                weight: transmittance.multiply(1f32 / probabilities.dot(transmittance)),
+            }
+        }
+    }
+}
+
+pub struct MediumInteraction {
+    pub scattered: bool,
+    pub distance: f32,
+    //Throughput change of the path
+    pub weight: Vector3,
+}
+
+#[derive(Clone, Copy)]
+struct MediumEntry {
+    object: u32,
+    medium: Medium,
+}
+
+//Media a path is currently inside of, in the order they were entered
+#[derive(Clone)]
+pub struct MediumStack {
+    entries: Vec<MediumEntry>,
+}
+
+impl Default for MediumStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, object: u32) -> bool {
+        self.entries.iter().any(|e| e.object == object)
+    }
+
+    //Highest priority medium, ignoring the given object
+    fn top_excluding(&self, object: Option<u32>) -> Option<&Medium> {
+        let mut top: Option<&Medium> = None;
+        for entry in self.entries.iter() {
+            if Some(entry.object) == object {
+                continue;
+            }
+            match top {
+                Some(m) if m.priority > entry.medium.priority => (),
+                _ => top = Some(&entry.medium),
+            }
+        }
+        top
+    }
+
+    //Medium the path is travelling through
+    pub fn current(&self) -> Medium {
+        self.top_excluding(None)
+            .copied()
+            .unwrap_or_else(Medium::vacuum)
+    }
+
+    //Surfaces of lower priority media inside a higher priority one are not real interfaces
+    pub fn is_false_hit(&self, object: u32, medium: &Medium) -> bool {
+        match self.top_excluding(Some(object)) {
+            Some(top) => medium.priority < top.priority,
+            None => false,
+        }
+    }
+
+    //IOR of the far side of the surface of `object` divided by the IOR of the near side
+    pub fn eta(&self, object: u32, medium: &Medium) -> f32 {
+        let other = self
+            .top_excluding(Some(object))
+            .map(|m| m.ior)
+            .unwrap_or(1f32);
+        if self.contains(object) {
+            other / medium.ior
+        } else {
+            medium.ior / other
+        }
+    }
+
+    //Enters the medium of `object`, or leaves it if the path is already inside
+    pub fn cross(&mut self, object: u32, medium: Medium) {
+        match self.entries.iter().rposition(|e| e.object == object) {
+            Some(i) => {
+                self.entries.remove(i);
+            }
+            None => self.entries.push(MediumEntry { object, medium }),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::f32::consts::PI;
+
+use crate::utilities::{Matrix3, Vector3};
+
+//Henyey-Greenstein phase function. `g` is the mean cosine of the scattering angle,
+//positive values scatter forward and 0 scatters uniformly.
+
+//`cos` is the cosine between the directions before and after scattering
+pub fn henyey_greenstein(cos: f32, g: f32) -> f32 {
+    let denom = 1f32 + g * g - 2f32 * g * cos;
+    (1f32 - g * g) / (4f32 * PI * denom * denom.max(1e-8).sqrt())
+}
+
+//Samples a new direction for light travelling along `dir`
+pub fn sample_henyey_greenstein(dir: Vector3, g: f32) -> Vector3 {
+    let u = rand::random::<f32>();
+    let cos = if g.abs() < 1e-3 {
+        1f32 - 2f32 * u
+    } else {
+        let s = (1f32 - g * g) / (1f32 - g + 2f32 * g * u);
+        ((1f32 + g * g - s * s) / (2f32 * g)).clamp(-1f32, 1f32)
+    };
+    let sin = (1f32 - cos * cos).max(0f32).sqrt();
+    let phi = 2f32 * PI * rand::random::<f32>();
+    Matrix3::from_normal(dir).multiply(Vector3::new(sin * phi.cos(), sin * phi.sin(), cos))
+}
@@ -0,0 +1,192 @@
+use std::{convert::TryInto, fs, path::Path};
+
+use crate::{error::Error, noise, ray_resolvers::bvh::aabb::AABB, utilities::Vector3};
+
+//Dense grid of densities. Files start with the resolution as three little endian u32s,
+//followed by a little endian f32 for every voxel with x varying fastest, then y, then z.
+#[derive(Clone)]
+pub struct DensityGrid {
+    pub resolution: [usize; 3],
+    pub values: Vec<f32>,
+}
+
+impl DensityGrid {
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> Result<Self, Error> {
+        let bytes = fs::read(filename)?;
+        let word = |i: usize| -> Option<[u8; 4]> { bytes.get(i * 4..i * 4 + 4)?.try_into().ok() };
+        let mut resolution = [0usize; 3];
+        for (i, r) in resolution.iter_mut().enumerate() {
+            let w = word(i)
+                .ok_or_else(|| Error::new("Density grid is missing its header".to_string()))?;
+            *r = u32::from_le_bytes(w) as usize;
+        }
+        let wrong_size = || {
+            Error::new(format!(
+                "Density grid of resolution {:?} has the wrong size",
+                resolution
+            ))
+        };
+        //Untrusted headers can describe grids too large to be indexed
+        let count = resolution[0]
+            .checked_mul(resolution[1])
+            .and_then(|c| c.checked_mul(resolution[2]))
+            .ok_or_else(wrong_size)?;
+        let size = count
+            .checked_add(3)
+            .and_then(|c| c.checked_mul(4))
+            .ok_or_else(wrong_size)?;
+        if count == 0 || bytes.len() != size {
+            return Err(wrong_size());
+        }
+        let values = (3..count + 3)
+            .map(|i| f32::from_le_bytes(word(i).unwrap()))
+            .collect();
+        Ok(Self { resolution, values })
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f32 {
+        let [rx, ry, _] = self.resolution;
+        self.values[x + rx * (y + ry * z)]
+    }
+
+    //Trilinearly interpolated density at a position inside the unit cube
+    pub fn sample(&self, p: Vector3) -> f32 {
+        let coordinate = |v: f32, r: usize| {
+            let v = (v * r as f32 - 0.5).clamp(0f32, (r - 1) as f32);
+            let i = (v as usize).min(r.saturating_sub(2));
+            (i, (i + 1).min(r - 1), v - i as f32)
+        };
+        let (x0, x1, fx) = coordinate(p.x, self.resolution[0]);
+        let (y0, y1, fy) = coordinate(p.y, self.resolution[1]);
+        let (z0, z1, fz) = coordinate(p.z, self.resolution[2]);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(self.voxel(x0, y0, z), self.voxel(x1, y0, z), fx),
+                lerp(self.voxel(x0, y1, z), self.voxel(x1, y1, z), fx),
+                fy,
+            )
+        };
+        lerp(plane(z0), plane(z1), fz)
+    }
+
+    pub fn max(&self) -> f32 {
+        self.values.iter().cloned().fold(0f32, f32::max)
+    }
+}
+
+#[derive(Clone)]
+pub enum Density {
+    //Fractal noise in world space. Only the densest `coverage` fraction of its range is kept,
+    //rescaled to [0, 1]
+    Noise {
+        frequency: f32,
+        octaves: u32,
+        coverage: f32,
+    },
+    //Grid stretched over the bounds of the volume
+    Grid(DensityGrid),
+}
+
+//Bounded medium with spatially varying density
+#[derive(Clone)]
+pub struct Volume {
+    pub bounds: AABB,
+    pub density: Density,
+    //Extinction coefficient at a density of 1
+    pub extinction: f32,
+    //Fraction of extinction that scatters rather than absorbs
+    pub albedo: Vector3,
+    //Radiance emitted by the absorbing part of the medium, for fire
+    pub emission: Vector3,
+    max_density: f32,
+}
+
+impl Volume {
+    pub fn new(bounds: AABB, density: Density, extinction: f32, albedo: Vector3) -> Self {
+        let max_density = match &density {
+            Density::Noise { .. } => 1f32,
+            Density::Grid(grid) => grid.max(),
+        };
+        Self {
+            bounds,
+            density,
+            extinction,
+            albedo,
+            emission: Vector3::zero(),
+            max_density,
+        }
+    }
+
+    pub fn with_emission(self, emission: Vector3) -> Self {
+        Self { emission, ..self }
+    }
+
+    pub fn density_at(&self, p: Vector3) -> f32 {
+        match &self.density {
+            Density::Noise {
+                frequency,
+                octaves,
+                coverage,
+            } => {
+                let n = noise::fbm(p.multiply(*frequency), *octaves) * 0.5 + 0.5;
+                ((n - 1f32 + coverage) / coverage).clamp(0f32, 1f32)
+            }
+            Density::Grid(grid) => {
+                let local = p
+                    .subtract(self.bounds.min)
+                    .comp_multiply(self.bounds.size().reciprocal());
+                grid.sample(local)
+            }
+        }
+    }
+
+    fn majorant(&self) -> f32 {
+        self.extinction * self.max_density
+    }
+
+    //Part of the ray within the bounds, clipped to `t_max`
+    fn segment(&self, pos: Vector3, dir: Vector3, t_max: f32) -> Option<(f32, f32)> {
+        let (t0, t1) = self.bounds.interval(&pos, &dir)?;
+        let (t0, t1) = (t0.max(0f32), t1.min(t_max));
+        if t0 >= t1 || self.majorant() <= 0f32 {
+            return None;
+        }
+        Some((t0, t1))
+    }
+
+    //Distance to the first real collision along the ray before `t_max`, found by delta tracking
+    pub fn sample_collision(&self, pos: Vector3, dir: Vector3, t_max: f32) -> Option<f32> {
+        let (mut t, t1) = self.segment(pos, dir, t_max)?;
+        let majorant = self.majorant();
+        loop {
+            t -= (1f32 - rand::random::<f32>()).ln() / majorant;
+            if t >= t1 {
+                return None;
+            }
+            let density = self.density_at(pos.add(dir.multiply(t)));
+            if rand::random::<f32>() * self.max_density < density {
+                return Some(t);
+            }
+        }
+    }
+
+    //Fraction of light passing through the volume along the ray before `t_max`,
+    //estimated by ratio tracking
+    pub fn transmittance(&self, pos: Vector3, dir: Vector3, t_max: f32) -> f32 {
+        let (mut t, t1) = match self.segment(pos, dir, t_max) {
+            Some(segment) => segment,
+            None => return 1f32,
+        };
+        let majorant = self.majorant();
+        let mut transmittance = 1f32;
+        loop {
+            t -= (1f32 - rand::random::<f32>()).ln() / majorant;
+            if t >= t1 {
+                return transmittance;
+            }
+            let density = self.density_at(pos.add(dir.multiply(t)));
+            transmittance *= 1f32 - density / self.max_density;
+        }
+    }
+}
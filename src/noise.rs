@@ -0,0 +1,83 @@
+use crate::utilities::Vector3;
+
+//Gradient noise, returning values in roughly [-1, 1]
+
+const GRADIENTS: [[f32; 3]; 12] = [
+    [1f32, 1f32, 0f32],
+    [-1f32, 1f32, 0f32],
+    [1f32, -1f32, 0f32],
+    [-1f32, -1f32, 0f32],
+    [1f32, 0f32, 1f32],
+    [-1f32, 0f32, 1f32],
+    [1f32, 0f32, -1f32],
+    [-1f32, 0f32, -1f32],
+    [0f32, 1f32, 1f32],
+    [0f32, -1f32, 1f32],
+    [0f32, 1f32, -1f32],
+    [0f32, -1f32, -1f32],
+];
+
+pub fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+fn gradient(x: i32, y: i32, z: i32, offset: Vector3) -> f32 {
+    let g = GRADIENTS[(hash(x, y, z) % 12) as usize];
+    g[0] * offset.x + g[1] * offset.y + g[2] * offset.z
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6f32 - 15f32) + 10f32)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+pub fn perlin(p: Vector3) -> f32 {
+    let (x, y, z) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let f = p.subtract(Vector3::new(x, y, z));
+    let (x, y, z) = (x as i32, y as i32, z as i32);
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        gradient(
+            x + dx,
+            y + dy,
+            z + dz,
+            f.subtract(Vector3::new(dx as f32, dy as f32, dz as f32)),
+        )
+    };
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    lerp(
+        lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), u),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), u),
+            v,
+        ),
+        lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), u),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), u),
+            v,
+        ),
+        w,
+    )
+}
+
+//Fractal sum of octaves of noise, each twice the frequency and half the amplitude
+pub fn fbm(p: Vector3, octaves: u32) -> f32 {
+    let mut sum = 0f32;
+    let mut amplitude = 1f32;
+    let mut total = 0f32;
+    let mut p = p;
+    for _ in 0..octaves.max(1) {
+        sum += perlin(p) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        p = p.multiply(2f32);
+    }
+    sum / total
+}
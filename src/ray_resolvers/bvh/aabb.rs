@@ -1,4 +1,6 @@
 use crate::{
+    differentials::RayDifferential,
+    media::MediumStack,
     ray_resolvers::ray_resolver::{RayResolver, RayResult},
     utilities::{SceneData, Vector3},
 };
@@ -11,6 +13,16 @@ pub struct AABB {
 
 impl AABB {
     pub fn trace(&self, pos: &Vector3, dir: &Vector3) -> Option<Vector3> {
+        let (tmin, tmax) = self.interval(pos, dir)?;
+        let t = if tmin < 0.0 { tmax } else { tmin };
+        if tmax < 0.0 {
+            return None;
+        }
+        Some(dir.multiply(t).add(pos.clone()))
+    }
+
+    //Distances along the ray at which it enters and leaves the box
+    pub fn interval(&self, pos: &Vector3, dir: &Vector3) -> Option<(f32, f32)> {
         let invdir = dir.reciprocal();
         let (mut tmin, mut tmax) = if invdir.x >= 0.0 {
             let tmin = (self.min.x - pos.x) * invdir.x;
@@ -61,11 +73,7 @@ impl AABB {
             tmax = tzmax;
         }
 
-        let t = if tmin < 0.0 { tmax } else { tmin };
-        if tmax < 0.0 {
-            return None;
-        }
-        Some(dir.multiply(t).add(pos.clone()))
+        Some((tmin, tmax))
     }
 
     pub fn union(&self, other: &Self) -> Self {
@@ -128,11 +136,12 @@ impl RayResolver for AABBRayResolver {
         &self,
         pos: Vector3,
         dir: Vector3,
-        refraction: bool,
+        differential: Option<&RayDifferential>,
+        media: &MediumStack,
         scene: SceneData,
     ) -> Option<RayResult> {
         match self.aabb.trace(&pos, &dir) {
-            Some(_) => self.inner.resolve(pos, dir, refraction, scene),
+            Some(_) => self.inner.resolve(pos, dir, differential, media, scene),
             None => None,
         }
     }
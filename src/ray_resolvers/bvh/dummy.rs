@@ -1,5 +1,8 @@
 use crate::{
-    ray_resolvers::ray_resolver::{MaterialType, RayResolver, RayResult},
+    differentials::RayDifferential,
+    materials::principled::Material,
+    media::MediumStack,
+    ray_resolvers::ray_resolver::{RayResolver, RayResult},
     utilities::{SceneData, Vector3},
 };
 
@@ -10,15 +13,15 @@ impl RayResolver for Dummy {
         &self,
         pos: Vector3,
         dir: Vector3,
-        _refraction: bool,
+        _differential: Option<&RayDifferential>,
+        _media: &MediumStack,
         _scene: SceneData,
     ) -> Option<RayResult> {
         Some(RayResult::new(
             pos,
-            Vector3::from_single(1.0),
             Vector3::zero().subtract(dir),
-            Vector3::zero(),
-            MaterialType::Diffuse,
+            Material::diffuse(Vector3::from_single(1.0)),
+            0,
         ))
     }
 }
@@ -0,0 +1,441 @@
+use std::{collections::HashMap, path::Path, sync::Arc};
+
+use gltf::{
+    buffer,
+    camera::Projection,
+    image::{self, Format},
+    khr_lights_punctual::Kind,
+    material::AlphaMode as GltfAlphaMode,
+    mesh::Mode,
+    texture::WrappingMode,
+    Node,
+};
+
+use crate::{
+    error::Error,
+    lights::Light,
+    materials::principled::Material,
+    textures::{
+        procedural::{Coordinates, Texture, TextureNode},
+        AlphaMode, ImageTexture, MaterialTextures, WrapMode, ALPHA_CUTOFF,
+    },
+    utilities::Vector3,
+};
+
+use super::{
+    imported_triangle, tangents::generate_tangents, triangle::Triangle, Corner, ImportOptions,
+};
+
+//Camera of a glTF scene, looking from `position` towards `target`
+pub struct GltfCamera {
+    pub position: Vector3,
+    pub target: Vector3,
+    //Vertical field of view in radians, if the camera is a perspective one
+    pub yfov: Option<f32>,
+}
+
+//Meshes, punctual lights and cameras of a glTF scene, placed by the node hierarchy
+pub struct GltfScene {
+    pub triangles: Vec<Triangle>,
+    pub lights: Vec<Light>,
+    pub cameras: Vec<GltfCamera>,
+}
+
+//Affine transform of a node, given by the columns of its linear part and its translation
+#[derive(Clone, Copy)]
+struct Affine {
+    columns: [Vector3; 3],
+    translation: Vector3,
+}
+
+impl Affine {
+    fn identity() -> Self {
+        Self {
+            columns: [
+                Vector3::new(1f32, 0f32, 0f32),
+                Vector3::new(0f32, 1f32, 0f32),
+                Vector3::new(0f32, 0f32, 1f32),
+            ],
+            translation: Vector3::zero(),
+        }
+    }
+
+    //Column major 4x4 matrix, as glTF stores them
+    fn from_matrix(m: [[f32; 4]; 4]) -> Self {
+        let column = |c: [f32; 4]| Vector3::new(c[0], c[1], c[2]);
+        Self {
+            columns: [column(m[0]), column(m[1]), column(m[2])],
+            translation: column(m[3]),
+        }
+    }
+
+    fn direction(&self, v: Vector3) -> Vector3 {
+        self.columns[0]
+            .multiply(v.x)
+            .add(self.columns[1].multiply(v.y))
+            .add(self.columns[2].multiply(v.z))
+    }
+
+    fn point(&self, v: Vector3) -> Vector3 {
+        self.direction(v).add(self.translation)
+    }
+
+    //Transform applied after `child`
+    fn then(&self, child: &Affine) -> Self {
+        Self {
+            columns: [
+                self.direction(child.columns[0]),
+                self.direction(child.columns[1]),
+                self.direction(child.columns[2]),
+            ],
+            translation: self.point(child.translation),
+        }
+    }
+
+    fn determinant(&self) -> f32 {
+        self.columns[0].dot(self.columns[1].cross(self.columns[2]))
+    }
+
+    //Normals are transformed by the inverse transpose, which is proportional to the cofactors
+    fn normal(&self, n: Vector3) -> Vector3 {
+        let [c0, c1, c2] = self.columns;
+        c1.cross(c2)
+            .multiply(n.x)
+            .add(c2.cross(c0).multiply(n.y))
+            .add(c0.cross(c1).multiply(n.z))
+            .multiply(self.determinant().signum())
+            .normalized()
+    }
+}
+
+type ImageCache = HashMap<(usize, bool, [WrapMode; 2]), Arc<ImageTexture>>;
+
+//Converts decoded image data. Color images are stored in sRGB unless they are floating point
+fn convert_image(data: &image::Data, wrap: [WrapMode; 2], color: bool) -> ImageTexture {
+    let (channels, bytes, float) = match data.format {
+        Format::R8 => (1, 1, false),
+        Format::R8G8 => (2, 1, false),
+        Format::R8G8B8 => (3, 1, false),
+        Format::R8G8B8A8 => (4, 1, false),
+        Format::R16 => (1, 2, false),
+        Format::R16G16 => (2, 2, false),
+        Format::R16G16B16 => (3, 2, false),
+        Format::R16G16B16A16 => (4, 2, false),
+        Format::R32G32B32FLOAT => (3, 4, true),
+        Format::R32G32B32A32FLOAT => (4, 4, true),
+    };
+    let value = |i: usize| {
+        let b = &data.pixels[i * bytes..(i + 1) * bytes];
+        match bytes {
+            1 => b[0] as f32 / 255f32,
+            2 => u16::from_ne_bytes([b[0], b[1]]) as f32 / 65535f32,
+            _ => f32::from_ne_bytes([b[0], b[1], b[2], b[3]]),
+        }
+    };
+    let count = (data.width * data.height) as usize;
+    let pixels = (0..count)
+        .map(|p| {
+            //Images with one or two channels are grayscale, with alpha in the second one
+            let v = if channels < 3 {
+                Vector3::from_single(value(p * channels))
+            } else {
+                Vector3::new(
+                    value(p * channels),
+                    value(p * channels + 1),
+                    value(p * channels + 2),
+                )
+            };
+            if color && !float {
+                v.srgb()
+            } else {
+                v
+            }
+        })
+        .collect();
+    let alpha = match channels {
+        2 | 4 => Some(
+            (0..count)
+                .map(|p| value(p * channels + channels - 1))
+                .collect(),
+        ),
+        _ => None,
+    };
+    ImageTexture::new(
+        data.width as usize,
+        data.height as usize,
+        pixels,
+        alpha,
+        wrap,
+    )
+}
+
+fn texture_image(
+    texture: &gltf::Texture,
+    images: &[image::Data],
+    color: bool,
+    cache: &mut ImageCache,
+) -> Arc<ImageTexture> {
+    let index = texture.source().index();
+    let wrap_mode = |mode: WrappingMode| match mode {
+        WrappingMode::ClampToEdge => WrapMode::Clamp,
+        WrappingMode::MirroredRepeat => WrapMode::Mirror,
+        WrappingMode::Repeat => WrapMode::Repeat,
+    };
+    let sampler = texture.sampler();
+    let wrap = [wrap_mode(sampler.wrap_s()), wrap_mode(sampler.wrap_t())];
+    cache
+        .entry((index, color, wrap))
+        .or_insert_with(|| Arc::new(convert_image(&images[index], wrap, color)))
+        .clone()
+}
+
+//Image multiplied by a factor, as glTF materials combine them
+fn scaled_image(image: Arc<ImageTexture>, factor: Vector3) -> Texture {
+    Texture::new(
+        TextureNode::multiply(TextureNode::image(image), TextureNode::constant(factor)),
+        Coordinates::Uv,
+    )
+}
+
+//Single channel of an image multiplied by a factor
+fn scaled_channel(image: Arc<ImageTexture>, channel: usize, factor: f32) -> Texture {
+    Texture::new(
+        TextureNode::multiply(
+            TextureNode::channel(TextureNode::image(image), channel),
+            TextureNode::constant(Vector3::from_single(factor)),
+        ),
+        Coordinates::Uv,
+    )
+}
+
+//Converts a metallic-roughness material. Occlusion maps are ignored, as occlusion is traced
+pub fn material_from_gltf(
+    material: &gltf::Material,
+    images: &[image::Data],
+    cache: &mut ImageCache,
+) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, alpha] = pbr.base_color_factor();
+    let base_color = Vector3::new(r, g, b);
+    let base_image = pbr
+        .base_color_texture()
+        .map(|info| texture_image(&info.texture(), images, true, cache));
+    //Green holds roughness and blue holds metalness
+    let metallic_roughness = pbr
+        .metallic_roughness_texture()
+        .map(|info| texture_image(&info.texture(), images, false, cache));
+    let emissive = Vector3::from_slice(&material.emissive_factor());
+    let emission_image = material
+        .emissive_texture()
+        .map(|info| texture_image(&info.texture(), images, true, cache));
+    let emission = Material::textured_emission(emissive, emission_image.as_deref());
+    let alpha_mode = match material.alpha_mode() {
+        GltfAlphaMode::Opaque => None,
+        GltfAlphaMode::Mask => Some(AlphaMode::Mask(
+            material.alpha_cutoff().unwrap_or(ALPHA_CUTOFF),
+        )),
+        GltfAlphaMode::Blend => Some(AlphaMode::Blend),
+    };
+    //Coverage is the alpha of the base color
+    let alpha_texture = match (alpha_mode, &base_image) {
+        (Some(_), Some(image)) if image.levels[0].alpha.is_some() => Some(image.clone()),
+        _ => None,
+    };
+    let textures = MaterialTextures {
+        base_color: base_image.map(|image| scaled_image(image, base_color)),
+        roughness: metallic_roughness
+            .clone()
+            .map(|image| scaled_channel(image, 1, pbr.roughness_factor())),
+        metallic: metallic_roughness.map(|image| scaled_channel(image, 2, pbr.metallic_factor())),
+        emission: emission_image.map(|image| scaled_image(image, emissive)),
+        normal: material
+            .normal_texture()
+            .map(|normal| texture_image(&normal.texture(), images, false, cache)),
+        alpha: alpha_texture,
+        alpha_factor: if alpha_mode.is_some() { alpha } else { 1f32 },
+        alpha_mode: alpha_mode.unwrap_or(AlphaMode::Mask(ALPHA_CUTOFF)),
+        ..Default::default()
+    };
+    let transmission = material
+        .transmission()
+        .map(|t| t.transmission_factor())
+        .unwrap_or(0f32);
+    let converted = Material {
+        base_color,
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        transmission: transmission.clamp(0f32, 1f32),
+        ior: material.ior().unwrap_or(1.5),
+        double_sided: material.double_sided(),
+        //COLOR_0 multiplies the base color
+        multiply_vertex_color: true,
+        emission,
+        emission_strength: if emission.largest_component_value() > 0f32 {
+            material.emissive_strength().unwrap_or(1f32)
+        } else {
+            0f32
+        },
+        ..Default::default()
+    };
+    if textures.is_empty() {
+        converted
+    } else {
+        converted.with_textures(textures)
+    }
+}
+
+struct Loader {
+    buffers: Vec<buffer::Data>,
+    images: Vec<image::Data>,
+    materials: HashMap<Option<usize>, Material>,
+    cache: ImageCache,
+    scene: GltfScene,
+    object: u32,
+    options: ImportOptions,
+}
+
+impl Loader {
+    fn visit(&mut self, node: &Node, parent: &Affine) -> Result<(), Error> {
+        let transform = parent.then(&Affine::from_matrix(node.transform().matrix()));
+        let origin = self.options.position(transform.point(Vector3::zero()));
+        //Cameras and lights point along -Z
+        let forward = self
+            .options
+            .direction(transform.direction(Vector3::new(0f32, 0f32, -1f32)))
+            .normalized();
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.add_primitive(&primitive, &transform)?;
+            }
+        }
+        if let Some(camera) = node.camera() {
+            let yfov = match camera.projection() {
+                Projection::Perspective(perspective) => Some(perspective.yfov()),
+                Projection::Orthographic(_) => None,
+            };
+            self.scene.cameras.push(GltfCamera {
+                position: origin,
+                target: origin.add(forward),
+                yfov,
+            });
+        }
+        if let Some(light) = node.light() {
+            let color = Vector3::from_slice(&light.color());
+            let intensity = light.intensity();
+            self.scene.lights.push(match light.kind() {
+                Kind::Directional => Light::directional(forward, 0f32, color, intensity),
+                Kind::Point => Light::point(origin, color, intensity),
+                Kind::Spot {
+                    inner_cone_angle,
+                    outer_cone_angle,
+                } => {
+                    let blend = if outer_cone_angle > 0f32 {
+                        1f32 - inner_cone_angle / outer_cone_angle
+                    } else {
+                        0f32
+                    };
+                    Light::spot(origin, forward, outer_cone_angle, blend, color, intensity)
+                }
+            });
+        }
+        for child in node.children() {
+            self.visit(&child, &transform)?;
+        }
+        Ok(())
+    }
+
+    fn add_primitive(
+        &mut self,
+        primitive: &gltf::Primitive,
+        transform: &Affine,
+    ) -> Result<(), Error> {
+        //Points and lines have no surface
+        if !matches!(primitive.mode(), Mode::Triangles) {
+            return Ok(());
+        }
+        let buffers = &self.buffers;
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => return Ok(()),
+        };
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|n| n.collect());
+        let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|t| t.into_f32().collect());
+        //Vertex colors are linear in glTF
+        let colors: Option<Vec<[f32; 3]>> =
+            reader.read_colors(0).map(|c| c.into_rgb_f32().collect());
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        if indices.iter().any(|i| *i as usize >= positions.len()) {
+            return Err(Error::new(
+                "glTF primitive indexes missing vertices".to_string(),
+            ));
+        }
+        let gltf_material = primitive.material();
+        let (images, cache) = (&self.images, &mut self.cache);
+        let material = self
+            .materials
+            .entry(gltf_material.index())
+            .or_insert_with(|| material_from_gltf(&gltf_material, images, cache));
+        let corner = |i: u32| {
+            let i = i as usize;
+            Corner {
+                position: transform.point(Vector3::from_slice(&positions[i])),
+                normal: normals
+                    .as_ref()
+                    .map(|n| transform.normal(Vector3::from_slice(&n[i]))),
+                //Texture coordinates start from the top of the image
+                uv: uvs.as_ref().map(|uv| [uv[i][0], 1f32 - uv[i][1]]),
+                color: colors.as_ref().map(|c| Vector3::from_slice(&c[i])),
+            }
+        };
+        //Mirroring transforms reverse the winding
+        let mirrored = transform.determinant() < 0f32;
+        let first = self.scene.triangles.len();
+        for face in indices.chunks_exact(3) {
+            let corners = if mirrored {
+                [corner(face[0]), corner(face[2]), corner(face[1])]
+            } else {
+                [corner(face[0]), corner(face[1]), corner(face[2])]
+            };
+            let triangle = imported_triangle(corners, material, &self.options);
+            self.scene.triangles.push(triangle.with_object(self.object));
+        }
+        if uvs.is_some() {
+            generate_tangents(&mut self.scene.triangles[first..]);
+        }
+        self.object += 1;
+        Ok(())
+    }
+}
+
+//Loads the default scene of a .gltf or .glb file, or its first one if there is no default
+pub fn scene_from_gltf<P: AsRef<Path>>(
+    filename: P,
+    options: &ImportOptions,
+) -> Result<GltfScene, Error> {
+    let (document, buffers, images) = gltf::import(filename)?;
+    let gltf_scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| Error::new("glTF file has no scenes".to_string()))?;
+    let mut loader = Loader {
+        buffers,
+        images,
+        materials: HashMap::new(),
+        cache: HashMap::new(),
+        scene: GltfScene {
+            triangles: Vec::new(),
+            lights: Vec::new(),
+            cameras: Vec::new(),
+        },
+        object: 0,
+        options: *options,
+    };
+    for node in gltf_scene.nodes() {
+        loader.visit(&node, &Affine::identity())?;
+    }
+    Ok(loader.scene)
+}
@@ -1,40 +1,545 @@
 use core::fmt;
-use std::{cmp::Ordering, path::Path, usize};
+use std::{cmp::Ordering, collections::HashMap, path::Path, sync::Arc, usize};
 
 use crate::{
     error::Error,
-    ray_resolvers::{
-        bvh::{
-            multi_ray_resolver::MultiRayResolver,
-            triangle::{TriangleMaterial, TriangleResolver},
-        },
-        ray_resolver::MaterialType,
+    lights::light_tree::Emitters,
+    materials::principled::Material,
+    ray_resolvers::bvh::{multi_ray_resolver::MultiRayResolver, triangle::TriangleResolver},
+    textures::{
+        procedural::{Coordinates, Texture, TextureNode},
+        AlphaMode, ImageTexture, MaterialTextures, WrapMode, ALPHA_CUTOFF,
     },
     utilities::Vector3,
 };
 
 use self::{
     aabb::{AABBRayResolver, AABB},
+    gltf_scene::{scene_from_gltf, GltfScene},
+    ply::triangles_from_ply,
+    stl::triangles_from_stl,
+    tangents::generate_tangents,
     triangle::Triangle,
 };
 
 pub mod aabb;
 pub mod dummy;
+pub mod gltf_scene;
 pub mod multi_ray_resolver;
+pub mod ply;
+pub mod stl;
+pub mod tangents;
 pub mod triangle;
 
+//Corner of a triangle read from a file
+pub struct Corner {
+    pub position: Vector3,
+    pub normal: Option<Vector3>,
+    pub uv: Option<[f32; 2]>,
+    pub color: Option<Vector3>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum UpAxis {
+    Y,
+    Z,
+}
+
+//Order the corners of a front face are listed in, seen from the front
+#[derive(Clone, Copy, PartialEq)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise,
+}
+
+//Conventions of a mesh file, used to convert it to the left-handed, Y-up coordinates of
+//the scene. The defaults match OBJ and glTF files: right-handed, Y-up, in meters and
+//counter-clockwise
+#[derive(Clone, Copy)]
+pub struct ImportOptions {
+    pub handedness: Handedness,
+    pub up_axis: UpAxis,
+    //Scene units per file unit, such as 0.001 for millimeters
+    pub scale: f32,
+    pub winding: Winding,
+    //Whether vertex colors tint the base color rather than replace it
+    pub multiply_vertex_colors: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            handedness: Handedness::Right,
+            up_axis: UpAxis::Y,
+            scale: 1f32,
+            winding: Winding::CounterClockwise,
+            multiply_vertex_colors: false,
+        }
+    }
+}
+
+impl ImportOptions {
+    pub fn with_handedness(self, handedness: Handedness) -> Self {
+        Self { handedness, ..self }
+    }
+
+    pub fn with_up_axis(self, up_axis: UpAxis) -> Self {
+        Self { up_axis, ..self }
+    }
+
+    pub fn with_scale(self, scale: f32) -> Self {
+        Self { scale, ..self }
+    }
+
+    pub fn with_winding(self, winding: Winding) -> Self {
+        Self { winding, ..self }
+    }
+
+    pub fn with_multiply_vertex_colors(self, multiply_vertex_colors: bool) -> Self {
+        Self {
+            multiply_vertex_colors,
+            ..self
+        }
+    }
+
+    //Converts a direction or normal from the coordinates of the file to the ones of the scene
+    pub fn direction(&self, v: Vector3) -> Vector3 {
+        //Rotating Z up keeps the handedness
+        let v = match self.up_axis {
+            UpAxis::Y => v,
+            UpAxis::Z => Vector3::new(v.x, v.z, -v.y),
+        };
+        match self.handedness {
+            Handedness::Right => Vector3::new(-v.x, v.y, v.z),
+            Handedness::Left => v,
+        }
+    }
+
+    pub fn position(&self, v: Vector3) -> Vector3 {
+        self.direction(v).multiply(self.scale)
+    }
+
+    //Whether the corners have to be reversed so that front faces keep facing outwards
+    fn reverses_winding(&self) -> bool {
+        (self.handedness == Handedness::Right) != (self.winding == Winding::Clockwise)
+    }
+}
+
+//Triangle from corners in the order of the file. Normals are only used if every corner has one
+pub fn imported_triangle(
+    corners: [Corner; 3],
+    material: &Material,
+    options: &ImportOptions,
+) -> Triangle {
+    //Mirroring the coordinates reverses the winding
+    let [c0, c1, c2] = if options.reverses_winding() {
+        let [c2, c1, c0] = corners;
+        [c0, c1, c2]
+    } else {
+        corners
+    };
+    let (v0, v1, v2) = (
+        options.position(c0.position),
+        options.position(c1.position),
+        options.position(c2.position),
+    );
+    let material = Material {
+        multiply_vertex_color: material.multiply_vertex_color || options.multiply_vertex_colors,
+        ..material.clone()
+    };
+    let triangle = match (c0.normal, c1.normal, c2.normal) {
+        (Some(n0), Some(n1), Some(n2)) => Triangle::new_with_normal(
+            v0,
+            v1,
+            v2,
+            options.direction(n1),
+            options.direction(n2),
+            options.direction(n0),
+            material,
+        ),
+        _ => Triangle::new(v0, v1, v2, material),
+    };
+    let triangle = match (c0.uv, c1.uv, c2.uv) {
+        (Some(uv0), Some(uv1), Some(uv2)) => triangle.with_uvs(uv0, uv1, uv2),
+        _ => triangle,
+    };
+    match (c0.color, c1.color, c2.color) {
+        (Some(color0), Some(color1), Some(color2)) => triangle.with_colors(color0, color1, color2),
+        _ => triangle,
+    }
+}
+
 pub fn generate_bvh_from_file<P: AsRef<Path> + fmt::Debug>(
     filename: P,
+    options: &ImportOptions,
 ) -> Result<AABBRayResolver, Error> {
-    let triangles = triangles_from_file(filename)?;
+    let triangles = triangles_from_file(filename, options)?;
     generate_bvh(triangles)
 }
 
+//Color given as an unrecognized MTL parameter, such as `Ke`
+fn mtl_color(material: &tobj::Material, key: &str) -> Option<Vector3> {
+    let values = material
+        .unknown_param
+        .get(key)?
+        .split_whitespace()
+        .map(|v| v.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    match values.as_slice() {
+        [v] => Some(Vector3::from_single(*v)),
+        [r, g, b] => Some(Vector3::new(*r, *g, *b)),
+        _ => None,
+    }
+}
+
+//Image of an MTL texture statement, whose texture coordinates are multiplied by `scale` and
+//moved by `offset` as given by the `-s` and `-o` options
+struct MtlTexture {
+    image: Arc<ImageTexture>,
+    scale: [f32; 3],
+    offset: [f32; 3],
+}
+
+impl MtlTexture {
+    fn mapped<F: Fn(Vector3) -> Vector3>(self, f: F) -> Self {
+        Self {
+            image: Arc::new(self.image.mapped(f)),
+            ..self
+        }
+    }
+
+    fn is_transformed(&self) -> bool {
+        self.scale != [1f32; 3] || self.offset != [0f32; 3]
+    }
+
+    fn texture(self) -> Texture {
+        if !self.is_transformed() {
+            return Texture::image(self.image);
+        }
+        let node = TextureNode::image(self.image).scaled(
+            Vector3::new(self.scale[0], self.scale[1], self.scale[2]),
+            Vector3::new(self.offset[0], self.offset[1], self.offset[2]),
+        );
+        Texture::new(node, Coordinates::Uv)
+    }
+
+    //Texture multiplied by a color
+    fn scaled(self, factor: Vector3) -> Texture {
+        Texture::new(
+            TextureNode::multiply(self.texture().node, TextureNode::constant(factor)),
+            Coordinates::Uv,
+        )
+    }
+
+    //Normal, bump and alpha maps are sampled directly, so their coordinates can't be moved
+    fn image(self, map: &str) -> Result<Arc<ImageTexture>, Error> {
+        if self.is_transformed() {
+            return Err(Error::new(format!(
+                "The -s and -o options aren't supported for {}",
+                map
+            )));
+        }
+        Ok(self.image)
+    }
+}
+
+//Loads the image of an MTL texture statement, which may start with options
+fn mtl_texture(
+    statement: &str,
+    directory: &Path,
+    color: bool,
+    cache: &mut HashMap<String, Arc<ImageTexture>>,
+) -> Result<Option<MtlTexture>, Error> {
+    let mut wrap = WrapMode::Repeat;
+    let mut scale = [1f32; 3];
+    let mut offset = [0f32; 3];
+    let mut file = Vec::new();
+    let mut tokens = statement.split_whitespace().peekable();
+    while let Some(token) = tokens.next() {
+        match token {
+            "-clamp" => {
+                if tokens.next() == Some("on") {
+                    wrap = WrapMode::Clamp;
+                }
+            }
+            "-blendu" | "-blendv" | "-boost" | "-texres" | "-imfchan" | "-type" | "-cc" | "-bm" => {
+                tokens.next();
+            }
+            "-mm" => {
+                tokens.next();
+                tokens.next();
+            }
+            //Up to three numbers, the ones left out keeping their defaults
+            "-o" | "-s" | "-t" => {
+                let mut values = [None; 3];
+                for value in values.iter_mut() {
+                    *value = tokens.peek().and_then(|t| t.parse::<f32>().ok());
+                    if value.is_none() {
+                        break;
+                    }
+                    tokens.next();
+                }
+                let target = match token {
+                    "-o" => &mut offset,
+                    "-s" => &mut scale,
+                    _ => continue,
+                };
+                for (t, v) in target.iter_mut().zip(values.iter()) {
+                    if let Some(v) = v {
+                        *t = *v;
+                    }
+                }
+            }
+            _ => file.push(token),
+        }
+    }
+    if file.is_empty() {
+        return Ok(None);
+    }
+    let path = directory.join(file.join(" "));
+    let key = format!("{:?} {} {}", path, color, wrap as u8);
+    let image = match cache.get(&key) {
+        Some(image) => image.clone(),
+        None => {
+            let image = Arc::new(ImageTexture::from_file(path, [wrap; 2], color)?);
+            cache.insert(key, image.clone());
+            image
+        }
+    };
+    Ok(Some(MtlTexture {
+        image,
+        scale,
+        offset,
+    }))
+}
+
+//Multiplier given to a bump map with the `-bm` option
+fn mtl_bump_multiplier(statement: &str) -> f32 {
+    let mut tokens = statement.split_whitespace();
+    while let Some(token) = tokens.next() {
+        if token == "-bm" {
+            return tokens.next().and_then(|t| t.parse().ok()).unwrap_or(1f32);
+        }
+    }
+    1f32
+}
+
+//Whether a bump map statement holds tangent space normals rather than heights, as Blender
+//writes normal maps as bump maps. Files named like normal maps are taken as ones. Only for
+//other names the image is checked for an average pointing straight out of the surface
+fn is_normal_map(statement: &str, texture: &ImageTexture) -> bool {
+    let name = statement.to_lowercase();
+    if ["normal", "nrm", "_n."].iter().any(|n| name.contains(n)) {
+        return true;
+    }
+    let average = texture.average();
+    average.z > 0.7 && (average.x - 0.5).abs() < 0.1 && (average.y - 0.5).abs() < 0.1
+}
+
+//Blender writes the specular exponent as ((1 - roughness) * 30)^2
+fn roughness_from_shininess(shininess: f32) -> f32 {
+    (1f32 - shininess.max(0f32).sqrt() / 30f32).clamp(0f32, 1f32)
+}
+
+//Converts an MTL material the way Blender exports them, loading textures relative to `directory`
+pub fn material_from_mtl(
+    material: &tobj::Material,
+    directory: &Path,
+    cache: &mut HashMap<String, Arc<ImageTexture>>,
+) -> Result<Material, Error> {
+    let defaults = Material::default();
+    let base_color = Vector3::from_slice(&material.diffuse);
+    let roughness = roughness_from_shininess(material.shininess);
+    let specular_color = Vector3::from_slice(&material.specular);
+    let specular = specular_color.average();
+    //illum 0 and 1 have no highlights
+    let highlights = !matches!(material.illumination_model, Some(0) | Some(1));
+    //Ni defaults to 1, which would make glass invisible
+    let ior = if material.optical_density > 1f32 {
+        material.optical_density
+    } else {
+        defaults.ior
+    };
+    let opacity = match material.unknown_param.get("Tr") {
+        Some(tr) if material.dissolve >= 1f32 => 1f32 - tr.trim().parse::<f32>().unwrap_or(0f32),
+        _ => material.dissolve,
+    };
+    let opacity = opacity.clamp(0f32, 1f32);
+    let glass = matches!(
+        material.illumination_model,
+        Some(4) | Some(6) | Some(7) | Some(9)
+    );
+    let bump = match mtl_texture(&material.normal_texture, directory, false, cache)? {
+        Some(bump) => Some(bump.image("map_Bump")?),
+        None => None,
+    };
+    let (normal, bump) = match bump {
+        Some(bump) if is_normal_map(&material.normal_texture, &bump) => (Some(bump), None),
+        bump => (None, bump),
+    };
+    let normal = match material.unknown_param.get("norm") {
+        Some(statement) => match mtl_texture(statement, directory, false, cache)? {
+            Some(normal) => Some(normal.image("norm")?),
+            None => None,
+        },
+        None => normal,
+    };
+    let base_color_texture = mtl_texture(&material.diffuse_texture, directory, true, cache)?;
+    let alpha = match mtl_texture(&material.dissolve_texture, directory, false, cache)? {
+        Some(alpha) => Some(alpha.image("map_d")?),
+        //Without map_d the surface is cut out by the alpha channel of map_Kd, if it has one
+        //and is looked up at the untransformed texture coordinates the mask uses
+        None => base_color_texture
+            .as_ref()
+            .filter(|texture| !texture.is_transformed())
+            .map(|texture| texture.image.clone())
+            .filter(|image| image.levels[0].alpha.is_some()),
+    };
+    //map_Ns scales Ns, so its texels are converted to roughness like Ns is
+    let roughness_texture =
+        mtl_texture(&material.shininess_texture, directory, false, cache)?.map(|texture| {
+            let shininess = material.shininess;
+            texture.mapped(|c| Vector3::from_single(roughness_from_shininess(shininess * c.x)))
+        });
+    //map_Ks scales Ks
+    let specular_texture = if highlights {
+        mtl_texture(&material.specular_texture, directory, true, cache)?
+            .map(|texture| texture.mapped(|c| c.comp_multiply(specular_color)))
+    } else {
+        None
+    };
+    let emission_texture = match material.unknown_param.get("map_Ke") {
+        Some(statement) => mtl_texture(statement, directory, true, cache)?,
+        None => None,
+    };
+    //map_Ke scales Ke, which is taken as white when only the texture is given
+    let emission_color = mtl_color(material, "Ke").unwrap_or_else(|| match emission_texture {
+        Some(_) => Vector3::from_single(1f32),
+        None => Vector3::zero(),
+    });
+    let emission = Material::textured_emission(
+        emission_color,
+        emission_texture
+            .as_ref()
+            .map(|texture| texture.image.as_ref()),
+    );
+    let textures = MaterialTextures {
+        base_color: base_color_texture.map(MtlTexture::texture),
+        specular: specular_texture.map(MtlTexture::texture),
+        roughness: roughness_texture.map(MtlTexture::texture),
+        metallic: None,
+        emission: emission_texture.map(|texture| texture.scaled(emission_color)),
+        alpha,
+        normal,
+        bump,
+        bump_strength: mtl_bump_multiplier(&material.normal_texture),
+        //Dissolve is the coverage of the surface, as Blender writes alpha. Glass lets light
+        //through by refraction instead
+        alpha_factor: if glass { 1f32 } else { opacity },
+        alpha_mode: if glass || opacity >= 1f32 {
+            AlphaMode::Mask(ALPHA_CUTOFF)
+        } else {
+            AlphaMode::Blend
+        },
+    };
+    let textures = if textures.is_empty() {
+        None
+    } else {
+        Some(Arc::new(textures))
+    };
+    //Metalness of the PBR extension to MTL
+    let metallic = mtl_color(material, "Pm").map(|pm| pm.x.clamp(0f32, 1f32));
+    let converted = Material {
+        base_color,
+        metallic: metallic.unwrap_or(defaults.metallic),
+        roughness,
+        specular,
+        ior,
+        emission,
+        emission_strength: if emission.largest_component_value() > 0f32 {
+            1f32
+        } else {
+            0f32
+        },
+        textures,
+        ..defaults
+    };
+    let converted = match material.illumination_model {
+        //Color without highlights
+        _ if !highlights => Material {
+            specular: 0f32,
+            roughness: 1f32,
+            ..converted
+        },
+        //Ray traced reflections, which Blender also writes for partly metallic materials.
+        //Without an explicit metalness the specular color says how mirror-like they are
+        Some(3) | Some(5) | Some(8) if metallic.is_none() => Material {
+            metallic: specular.clamp(0f32, 1f32),
+            ..converted
+        },
+        //Glass, with dissolve setting how much of the light is refracted
+        _ if glass => Material {
+            transmission: if opacity < 1f32 { 1f32 - opacity } else { 1f32 },
+            ..converted
+        },
+        _ => converted,
+    };
+    Ok(converted)
+}
+
+fn extension<P: AsRef<Path>>(filename: &P) -> Option<String> {
+    filename
+        .as_ref()
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase)
+}
+
+//Loads a mesh, picking the format by the file's extension. Files are read as OBJ by default
 pub fn triangles_from_file<P: AsRef<Path> + fmt::Debug>(
     filename: P,
+    options: &ImportOptions,
 ) -> Result<Vec<Triangle>, Error> {
+    match extension(&filename).as_deref() {
+        Some("gltf") | Some("glb") => Ok(scene_from_gltf(filename, options)?.triangles),
+        Some("ply") => triangles_from_ply(filename, options),
+        Some("stl") => triangles_from_stl(filename, options),
+        _ => triangles_from_obj(filename, options),
+    }
+}
+
+//Loads a file as a scene. Only glTF files place lights and cameras in it
+pub fn scene_from_file<P: AsRef<Path> + fmt::Debug>(
+    filename: P,
+    options: &ImportOptions,
+) -> Result<GltfScene, Error> {
+    match extension(&filename).as_deref() {
+        Some("gltf") | Some("glb") => scene_from_gltf(filename, options),
+        _ => Ok(GltfScene {
+            triangles: triangles_from_file(filename, options)?,
+            lights: Vec::new(),
+            cameras: Vec::new(),
+        }),
+    }
+}
+
+pub fn triangles_from_obj<P: AsRef<Path> + fmt::Debug>(
+    filename: P,
+    options: &ImportOptions,
+) -> Result<Vec<Triangle>, Error> {
+    let directory = filename
+        .as_ref()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
     let (models, materials) = tobj::load_obj(
-        filename,
+        filename.as_ref(),
         &tobj::LoadOptions {
             single_index: true,
             triangulate: true,
@@ -49,15 +554,15 @@ pub fn triangles_from_file<P: AsRef<Path> + fmt::Debug>(
     } else {
         materials
     };
+    let mut cache = HashMap::new();
+    let materials = materials
+        .iter()
+        .map(|material| material_from_mtl(material, &directory, &mut cache))
+        .collect::<Result<Vec<_>, _>>()?;
     let mut triangles: Vec<Triangle> = Vec::new();
-    for model in models {
+    for (object, model) in models.into_iter().enumerate() {
         let material_index = model.mesh.material_id.unwrap_or(0);
         let material = materials.get(material_index).unwrap_or(&materials[0]);
-        let material = TriangleMaterial {
-            color: Vector3::from_slice(&material.diffuse),
-            emit: Vector3::zero(),
-            t: MaterialType::Diffuse,
-        };
         /*//Organize positions into Vector3s
         let positions: Vec<(Vector3,Vector3)> = model.mesh.positions
             .chunks(3)
@@ -80,54 +585,39 @@ pub fn triangles_from_file<P: AsRef<Path> + fmt::Debug>(
                 _ => panic!("Couldn't load mesh")
             }).collect();
         triangles.append(&mut t);*/
-        let has_normals = model.mesh.normals.len() > 0;
-        for _f in 0..model.mesh.indices.len() / 3 {
-            let i0 = model.mesh.indices[3 * _f + 0] as usize;
-            let i1 = model.mesh.indices[3 * _f + 1] as usize;
-            let i2 = model.mesh.indices[3 * _f + 2] as usize;
-            let v0 = Vector3::new(
-                -model.mesh.positions[i0 * 3 + 0],
-                model.mesh.positions[i0 * 3 + 1],
-                model.mesh.positions[i0 * 3 + 2],
-            );
-            let v1 = Vector3::new(
-                -model.mesh.positions[i1 * 3 + 0],
-                model.mesh.positions[i1 * 3 + 1],
-                model.mesh.positions[i1 * 3 + 2],
-            );
-            let v2 = Vector3::new(
-                -model.mesh.positions[i2 * 3 + 0],
-                model.mesh.positions[i2 * 3 + 1],
-                model.mesh.positions[i2 * 3 + 2],
-            );
-            if has_normals {
-                let n0 = Vector3::new(
-                    -model.mesh.normals[i0 * 3 + 0],
-                    model.mesh.normals[i0 * 3 + 1],
-                    model.mesh.normals[i0 * 3 + 2],
-                );
-                let n1 = Vector3::new(
-                    -model.mesh.normals[i1 * 3 + 0],
-                    model.mesh.normals[i1 * 3 + 1],
-                    model.mesh.normals[i1 * 3 + 2],
-                );
-                let n2 = Vector3::new(
-                    -model.mesh.normals[i2 * 3 + 0],
-                    model.mesh.normals[i2 * 3 + 1],
-                    model.mesh.normals[i2 * 3 + 2],
-                );
-                triangles.push(Triangle::new_with_normal(
-                    v2,
-                    v1,
-                    v0,
-                    n1,
-                    n0,
-                    n2,
-                    material.clone(),
-                ))
+        let has_texcoords = !model.mesh.texcoords.is_empty();
+        let first = triangles.len();
+        let mesh = &model.mesh;
+        let corner = |i: usize| Corner {
+            position: Vector3::from_slice(&mesh.positions[i * 3..i * 3 + 3]),
+            normal: if mesh.normals.is_empty() {
+                None
             } else {
-                triangles.push(Triangle::new(v2, v1, v0, material.clone()))
-            }
+                Some(Vector3::from_slice(&mesh.normals[i * 3..i * 3 + 3]))
+            },
+            uv: if mesh.texcoords.is_empty() {
+                None
+            } else {
+                Some([mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]])
+            },
+            //Vertex colors are stored in sRGB
+            color: if mesh.vertex_color.is_empty() {
+                None
+            } else {
+                Some(Vector3::from_slice(&mesh.vertex_color[i * 3..i * 3 + 3]).srgb())
+            },
+        };
+        for face in mesh.indices.chunks(3) {
+            let corners = [
+                corner(face[0] as usize),
+                corner(face[1] as usize),
+                corner(face[2] as usize),
+            ];
+            let triangle = imported_triangle(corners, material, options);
+            triangles.push(triangle.with_object(object as u32));
+        }
+        if has_texcoords {
+            generate_tangents(&mut triangles[first..]);
         }
     }
     Ok(triangles)
@@ -141,6 +631,14 @@ fn get_bounding_box(triangles: &Vec<Triangle>) -> AABB {
     aabb
 }
 
+//Builds the BVH along with a light tree over the emissive triangles
+pub fn generate_bvh_with_lights(
+    triangles: Vec<Triangle>,
+) -> Result<(AABBRayResolver, Emitters), Error> {
+    let (triangles, emitters) = Emitters::from_triangles(triangles);
+    Ok((generate_bvh(triangles)?, emitters))
+}
+
 pub fn generate_bvh(mut triangles: Vec<Triangle>) -> Result<AABBRayResolver, Error> {
     if triangles.len() < 1 {
         return Err(Error::new(
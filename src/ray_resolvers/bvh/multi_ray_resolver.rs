@@ -1,4 +1,6 @@
 use crate::{
+    differentials::RayDifferential,
+    media::MediumStack,
     ray_resolvers::ray_resolver::{RayResolver, RayResult},
     utilities::{SceneData, Vector3},
 };
@@ -12,7 +14,8 @@ impl RayResolver for MultiRayResolver {
         &self,
         pos: Vector3,
         dir: Vector3,
-        refraction: bool,
+        differential: Option<&RayDifferential>,
+        media: &MediumStack,
         scene: SceneData,
     ) -> Option<RayResult> {
         if self.inner.len() == 0 {
@@ -20,12 +23,12 @@ impl RayResolver for MultiRayResolver {
         }
         if self.inner.len() == 1 {
             let ray = &self.inner[0];
-            return ray.resolve(pos, dir, refraction, scene.clone());
+            return ray.resolve(pos, dir, differential, media, scene.clone());
         }
         let mut closest = None;
         let mut closest_distance = 0.0;
         for ray in &self.inner {
-            let result = ray.resolve(pos, dir, refraction, scene.clone());
+            let result = ray.resolve(pos, dir, differential, media, scene.clone());
             match result {
                 Some(result) => {
                     let distance = result.pos.subtract(pos).dot(dir);
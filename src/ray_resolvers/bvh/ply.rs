@@ -0,0 +1,292 @@
+use std::{convert::TryInto, fs, path::Path};
+
+use crate::{error::Error, materials::principled::Material, utilities::Vector3};
+
+use super::{imported_triangle, triangle::Triangle, Corner, ImportOptions};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    Int8,
+    Uint8,
+    Int16,
+    Uint16,
+    Int32,
+    Uint32,
+    Float32,
+    Float64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, Error> {
+        Ok(match name {
+            "char" | "int8" => Scalar::Int8,
+            "uchar" | "uint8" => Scalar::Uint8,
+            "short" | "int16" => Scalar::Int16,
+            "ushort" | "uint16" => Scalar::Uint16,
+            "int" | "int32" => Scalar::Int32,
+            "uint" | "uint32" => Scalar::Uint32,
+            "float" | "float32" => Scalar::Float32,
+            "double" | "float64" => Scalar::Float64,
+            _ => return Err(Error::new(format!("Unknown PLY type {}", name))),
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::Int8 | Scalar::Uint8 => 1,
+            Scalar::Int16 | Scalar::Uint16 => 2,
+            Scalar::Int32 | Scalar::Uint32 | Scalar::Float32 => 4,
+            Scalar::Float64 => 8,
+        }
+    }
+
+    //Largest value of integer types, which colors are given as fractions of
+    fn max(&self) -> Option<f64> {
+        match self {
+            Scalar::Int8 => Some(i8::MAX as f64),
+            Scalar::Uint8 => Some(u8::MAX as f64),
+            Scalar::Int16 => Some(i16::MAX as f64),
+            Scalar::Uint16 => Some(u16::MAX as f64),
+            Scalar::Int32 => Some(i32::MAX as f64),
+            Scalar::Uint32 => Some(u32::MAX as f64),
+            Scalar::Float32 | Scalar::Float64 => None,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    //Number of items followed by the items
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+//Reads the values of the body one after another
+struct Body<'a> {
+    data: &'a [u8],
+    position: usize,
+    encoding: Encoding,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, Error> {
+        if self.encoding == Encoding::Ascii {
+            return self.read_ascii();
+        }
+        let size = scalar.size();
+        let bytes = self
+            .data
+            .get(self.position..self.position + size)
+            .ok_or_else(|| Error::new("Unexpected end of PLY file".to_string()))?;
+        self.position += size;
+        let mut buffer = [0u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.encoding == Encoding::BigEndian {
+            buffer[..size].reverse();
+        }
+        let b = buffer;
+        Ok(match scalar {
+            Scalar::Int8 => b[0] as i8 as f64,
+            Scalar::Uint8 => b[0] as f64,
+            Scalar::Int16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::Uint16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Scalar::Int32 => i32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+            Scalar::Uint32 => u32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+            Scalar::Float32 => f32::from_le_bytes(b[..4].try_into().unwrap()) as f64,
+            Scalar::Float64 => f64::from_le_bytes(b),
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, Error> {
+        let rest = &self.data[self.position..];
+        let start = rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .ok_or_else(|| Error::new("Unexpected end of PLY file".to_string()))?;
+        let length = rest[start..]
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(rest.len() - start);
+        self.position += start + length;
+        let token = String::from_utf8_lossy(&rest[start..start + length]);
+        token
+            .parse()
+            .map_err(|_| Error::new(format!("Invalid PLY value {}", token)))
+    }
+}
+
+//Header lines up to and including `end_header`, along with the offset of the body
+fn read_header(data: &[u8]) -> Result<(Vec<String>, usize), Error> {
+    let mut lines = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let end = data[position..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map(|i| position + i)
+            .unwrap_or(data.len());
+        let line = String::from_utf8_lossy(&data[position..end])
+            .trim()
+            .to_string();
+        position = end + 1;
+        if line == "end_header" {
+            return Ok((lines, position));
+        }
+        lines.push(line);
+    }
+    Err(Error::new("PLY header is not terminated".to_string()))
+}
+
+fn parse_header(lines: &[String]) -> Result<(Encoding, Vec<Element>), Error> {
+    if lines.first().map(String::as_str) != Some("ply") {
+        return Err(Error::new("Not a PLY file".to_string()));
+    }
+    let mut encoding = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in &lines[1..] {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", format, ..] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::LittleEndian,
+                    "binary_big_endian" => Encoding::BigEndian,
+                    _ => return Err(Error::new(format!("Unknown PLY format {}", format))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| Error::new(format!("Invalid PLY element count {}", count)))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| Error::new("PLY property outside of an element".to_string()))?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", scalar, name] => elements
+                .last_mut()
+                .ok_or_else(|| Error::new("PLY property outside of an element".to_string()))?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(scalar)?)),
+            _ => {}
+        }
+    }
+    let encoding = encoding.ok_or_else(|| Error::new("PLY file has no format".to_string()))?;
+    Ok((encoding, elements))
+}
+
+//Vertex properties, in the order they are looked up by name
+const VERTEX_PROPERTIES: [&str; 11] = [
+    "x", "y", "z", "nx", "ny", "nz", "red", "green", "blue", "u", "v",
+];
+
+//Reads vertices and faces of an ASCII or binary PLY file. Polygons are split into fans of
+//triangles. Colors given as integers are scaled to [0, 1] by the range of their type, and all
+//colors are treated as sRGB
+pub fn triangles_from_ply<P: AsRef<Path>>(
+    filename: P,
+    options: &ImportOptions,
+) -> Result<Vec<Triangle>, Error> {
+    let data = fs::read(filename)?;
+    let (lines, offset) = read_header(&data)?;
+    let (encoding, elements) = parse_header(&lines)?;
+    let mut body = Body {
+        data: &data,
+        position: offset,
+        encoding,
+    };
+    let mut corners: Vec<[Option<f64>; 11]> = Vec::new();
+    let mut color_scale = 1f32;
+    let mut faces: Vec<Vec<usize>> = Vec::new();
+    for element in &elements {
+        for _ in 0..element.count {
+            let mut values = [None; 11];
+            let mut face = None;
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, scalar) => {
+                        let value = body.read(*scalar)?;
+                        //Texture coordinates also go by s and t
+                        let name = match name.as_str() {
+                            "s" | "texture_u" => "u",
+                            "t" | "texture_v" => "v",
+                            name => name,
+                        };
+                        if let Some(i) = VERTEX_PROPERTIES.iter().position(|p| *p == name) {
+                            values[i] = Some(value);
+                            if name == "red" {
+                                color_scale = scalar.max().map_or(1f32, |max| 1f32 / max as f32);
+                            }
+                        }
+                    }
+                    Property::List(name, count, item) => {
+                        let count = body.read(*count)? as usize;
+                        //The count is untrusted, so the list only grows as items are read
+                        let mut items = Vec::new();
+                        for _ in 0..count {
+                            items.push(body.read(*item)? as usize);
+                        }
+                        if name == "vertex_indices" || name == "vertex_index" {
+                            face = Some(items);
+                        }
+                    }
+                }
+            }
+            match element.name.as_str() {
+                "vertex" => corners.push(values),
+                "face" => faces.extend(face),
+                _ => {}
+            }
+        }
+    }
+    if corners.iter().any(|c| c[0..3].iter().any(Option::is_none)) {
+        return Err(Error::new("PLY vertices have no positions".to_string()));
+    }
+    let vector = |values: &[Option<f64>]| match values {
+        [Some(x), Some(y), Some(z)] => Some(Vector3::new(*x as f32, *y as f32, *z as f32)),
+        _ => None,
+    };
+    let corner = |i: usize| {
+        let values = &corners[i];
+        Corner {
+            position: vector(&values[0..3]).unwrap(),
+            normal: vector(&values[3..6]),
+            uv: match (values[9], values[10]) {
+                (Some(u), Some(v)) => Some([u as f32, v as f32]),
+                _ => None,
+            },
+            color: vector(&values[6..9]).map(|c| c.multiply(color_scale).srgb()),
+        }
+    };
+    let material = Material::default();
+    let mut triangles = Vec::new();
+    for face in faces {
+        if face.iter().any(|i| *i >= corners.len()) {
+            return Err(Error::new("PLY face indexes missing vertices".to_string()));
+        }
+        for i in 1..face.len().saturating_sub(1) {
+            let triangle = [corner(face[0]), corner(face[i]), corner(face[i + 1])];
+            triangles.push(imported_triangle(triangle, &material, options));
+        }
+    }
+    Ok(triangles)
+}
@@ -0,0 +1,82 @@
+use std::{convert::TryInto, fs, path::Path};
+
+use crate::{error::Error, materials::principled::Material, utilities::Vector3};
+
+use super::{imported_triangle, triangle::Triangle, Corner, ImportOptions};
+
+const HEADER_SIZE: usize = 84;
+const FACET_SIZE: usize = 50;
+
+fn corner(position: Vector3) -> Corner {
+    Corner {
+        position,
+        normal: None,
+        uv: None,
+        color: None,
+    }
+}
+
+fn read_binary(data: &[u8], count: usize) -> Vec<[Vector3; 3]> {
+    let float = |offset: usize| f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let vector = |offset: usize| Vector3::new(float(offset), float(offset + 4), float(offset + 8));
+    (0..count)
+        .map(|i| {
+            //Facet normals come first and are replaced by the geometric ones
+            let facet = HEADER_SIZE + i * FACET_SIZE + 12;
+            [vector(facet), vector(facet + 12), vector(facet + 24)]
+        })
+        .collect()
+}
+
+fn read_ascii(text: &str) -> Result<Vec<[Vector3; 3]>, Error> {
+    let mut facets = Vec::new();
+    let mut vertices = Vec::new();
+    let mut words = text.split_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "vertex" => {
+                let mut coordinate = || -> Result<f32, Error> {
+                    let word = words
+                        .next()
+                        .ok_or_else(|| Error::new("Unexpected end of STL file".to_string()))?;
+                    word.parse()
+                        .map_err(|_| Error::new(format!("Invalid STL value {}", word)))
+                };
+                vertices.push(Vector3::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            //Facets with more than three vertices are split into fans
+            "endfacet" => {
+                for i in 1..vertices.len().saturating_sub(1) {
+                    facets.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+                vertices.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+//Reads an ASCII or binary STL file. Binary files may also start with `solid`, so they are
+//told apart by their size matching the number of facets they declare
+pub fn triangles_from_stl<P: AsRef<Path>>(
+    filename: P,
+    options: &ImportOptions,
+) -> Result<Vec<Triangle>, Error> {
+    let data = fs::read(filename)?;
+    let count = data
+        .get(80..HEADER_SIZE)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize);
+    let facets = match count {
+        Some(count) if data.len() == HEADER_SIZE + count * FACET_SIZE => read_binary(&data, count),
+        _ if data.starts_with(b"solid") => read_ascii(&String::from_utf8_lossy(&data))?,
+        _ => return Err(Error::new("Invalid STL file".to_string())),
+    };
+    let material = Material::default();
+    Ok(facets
+        .into_iter()
+        .map(|[v0, v1, v2]| {
+            imported_triangle([corner(v0), corner(v1), corner(v2)], &material, options)
+        })
+        .collect())
+}
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::utilities::Vector3;
+
+use super::triangle::Triangle;
+
+//Identifies a corner shared between triangles, splitting it where the texture is mirrored
+type Corner = ([u32; 3], [u32; 3], [u32; 2], bool);
+
+fn corner(position: Vector3, normal: Vector3, uv: [f32; 2], handedness: f32) -> Corner {
+    (
+        [
+            position.x.to_bits(),
+            position.y.to_bits(),
+            position.z.to_bits(),
+        ],
+        [normal.x.to_bits(), normal.y.to_bits(), normal.z.to_bits()],
+        [uv[0].to_bits(), uv[1].to_bits()],
+        handedness > 0f32,
+    )
+}
+
+//Removes the part of `v` along the unit vector `n`
+fn orthogonalize(v: Vector3, n: Vector3) -> Vector3 {
+    v.subtract(n.multiply(v.dot(n)))
+}
+
+//Generates smooth per-corner tangents the way MikkTSpace does, by averaging the tangents of
+//the triangles sharing a corner weighted by the angle they make at it
+pub fn generate_tangents(triangles: &mut [Triangle]) {
+    let mut sums: HashMap<Corner, Vector3> = HashMap::new();
+    let mut faces = Vec::with_capacity(triangles.len());
+    for triangle in triangles.iter() {
+        let (dpdu, dpdv) = match triangle.uv_derivatives() {
+            Some(derivatives) => derivatives,
+            None => {
+                faces.push(None);
+                continue;
+            }
+        };
+        let face_normal = triangle
+            .v1
+            .subtract(triangle.v0)
+            .cross(triangle.v2.subtract(triangle.v0))
+            .normalized();
+        let handedness = if face_normal.cross(dpdu).dot(dpdv) < 0f32 {
+            -1f32
+        } else {
+            1f32
+        };
+        let positions = [triangle.v0, triangle.v1, triangle.v2];
+        let normals = triangle.vertex_normals();
+        let uvs = [triangle.uv0, triangle.uv1, triangle.uv2];
+        let mut keys = Vec::with_capacity(3);
+        for i in 0..3 {
+            let edge1 = positions[(i + 1) % 3].subtract(positions[i]).normalized();
+            let edge2 = positions[(i + 2) % 3].subtract(positions[i]).normalized();
+            let angle = edge1.dot(edge2).clamp(-1f32, 1f32).acos();
+            let tangent = orthogonalize(dpdu, normals[i]).normalized();
+            let key = corner(positions[i], normals[i], uvs[i], handedness);
+            let sum = sums.entry(key).or_insert_with(Vector3::zero);
+            *sum = sum.add(tangent.multiply(angle));
+            keys.push(key);
+        }
+        faces.push(Some((keys, handedness)));
+    }
+    for (triangle, face) in triangles.iter_mut().zip(faces) {
+        if let Some((keys, handedness)) = face {
+            let normals = triangle.vertex_normals();
+            let tangent = |i: usize| {
+                let t = orthogonalize(sums[&keys[i]], normals[i]).normalized();
+                //Corners whose tangents cancel out are left without one
+                if t.x.is_finite() {
+                    t
+                } else {
+                    Vector3::zero()
+                }
+            };
+            triangle.t0 = tangent(0);
+            triangle.t1 = tangent(1);
+            triangle.t2 = tangent(2);
+            triangle.handedness = handedness;
+        }
+    }
+}
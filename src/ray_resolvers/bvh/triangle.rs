@@ -1,18 +1,16 @@
 use std::{cmp::Ordering, f32::EPSILON};
 
 use crate::{
-    ray_resolvers::ray_resolver::{MaterialType, RayResolver, RayResult},
+    differentials::RayDifferential,
+    materials::principled::Material,
+    media::MediumStack,
+    ray_resolvers::ray_resolver::{RayResolver, RayResult, SurfaceDerivatives},
+    textures::Footprint,
     utilities::{SceneData, Vector3},
 };
 
 use super::aabb::AABB;
 
-#[derive(Clone)]
-pub struct TriangleMaterial {
-    pub color: Vector3,
-    pub emit: Vector3,
-    pub t: MaterialType,
-}
 #[derive(Clone)]
 pub struct Triangle {
     pub v0: Vector3,
@@ -21,12 +19,27 @@ pub struct Triangle {
     pub n0: Vector3,
     pub n1: Vector3,
     pub n2: Vector3,
+    //Texture coordinates of v0, v1 and v2
+    pub uv0: [f32; 2],
+    pub uv1: [f32; 2],
+    pub uv2: [f32; 2],
+    //Tangents of v0, v1 and v2 pointing along increasing u, zero when not generated
+    pub t0: Vector3,
+    pub t1: Vector3,
+    pub t2: Vector3,
+    //Sign of the bitangent relative to the normal cross the tangent
+    pub handedness: f32,
+    //Colors of v0, v1 and v2, if the mesh has them
+    pub colors: Option<[Vector3; 3]>,
     pub centroid: Vector3,
-    pub material: TriangleMaterial,
+    pub material: Material,
+    pub object: u32,
+    //Index among the scene's emitters, if the triangle emits light
+    pub light: Option<u32>,
 }
 
 impl Triangle {
-    pub fn new(v0: Vector3, v1: Vector3, v2: Vector3, material: TriangleMaterial) -> Self {
+    pub fn new(v0: Vector3, v1: Vector3, v2: Vector3, material: Material) -> Self {
         let v0v1 = v1.subtract(v0);
         let v0v2 = v2.subtract(v0);
         let normal = v0v1.cross(v0v2).normalized();
@@ -39,7 +52,7 @@ impl Triangle {
         n0: Vector3,
         n1: Vector3,
         n2: Vector3,
-        material: TriangleMaterial,
+        material: Material,
     ) -> Self {
         let centroid = v0.add(v1).add(v2).multiply(1.0 / 3.0);
         Self {
@@ -49,15 +62,161 @@ impl Triangle {
             n0,
             n1,
             n2,
+            uv0: [0f32; 2],
+            uv1: [0f32; 2],
+            uv2: [0f32; 2],
+            t0: Vector3::zero(),
+            t1: Vector3::zero(),
+            t2: Vector3::zero(),
+            handedness: 1f32,
+            colors: None,
             centroid,
             material,
+            object: 0,
+            light: None,
+        }
+    }
+
+    pub fn with_object(self, object: u32) -> Self {
+        Self { object, ..self }
+    }
+
+    pub fn with_light(self, light: u32) -> Self {
+        Self {
+            light: Some(light),
+            ..self
+        }
+    }
+
+    pub fn with_uvs(self, uv0: [f32; 2], uv1: [f32; 2], uv2: [f32; 2]) -> Self {
+        Self {
+            uv0,
+            uv1,
+            uv2,
+            ..self
+        }
+    }
+
+    pub fn with_colors(self, c0: Vector3, c1: Vector3, c2: Vector3) -> Self {
+        Self {
+            colors: Some([c0, c1, c2]),
+            ..self
         }
     }
+
+    //Normals of v0, v1 and v2, which are stored in a different order
+    pub fn vertex_normals(&self) -> [Vector3; 3] {
+        [self.n2, self.n0, self.n1]
+    }
+
+    //Normal of the plane of the triangle, facing the side its corners are counter-clockwise from
+    pub fn face_normal(&self) -> Vector3 {
+        let v0v1 = self.v1.subtract(self.v0);
+        let v0v2 = self.v2.subtract(self.v0);
+        v0v1.cross(v0v2).normalized()
+    }
+
+    //Derivatives of the position with respect to the texture coordinates
+    pub fn uv_derivatives(&self) -> Option<(Vector3, Vector3)> {
+        let e1 = self.v1.subtract(self.v0);
+        let e2 = self.v2.subtract(self.v0);
+        let (du1, dv1) = (self.uv1[0] - self.uv0[0], self.uv1[1] - self.uv0[1]);
+        let (du2, dv2) = (self.uv2[0] - self.uv0[0], self.uv2[1] - self.uv0[1]);
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let dpdu = e1
+            .multiply(dv2)
+            .subtract(e2.multiply(dv1))
+            .multiply(1f32 / det);
+        let dpdv = e2
+            .multiply(du1)
+            .subtract(e1.multiply(du2))
+            .multiply(1f32 / det);
+        Some((dpdu, dpdv))
+    }
+
+    //Tangent and bitangent at the given barycentric coordinates, scaled to the derivatives of
+    //the position with respect to the texture coordinates. Only needed for normal and bump maps
+    fn tangent_frame(&self, normal: Vector3, u: f32, v: f32) -> Option<(Vector3, Vector3)> {
+        let textures = self.material.textures.as_ref()?;
+        if !textures.perturbs_normal() {
+            return None;
+        }
+        let (dpdu, dpdv) = self.uv_derivatives()?;
+        let w = 1f32 - u - v;
+        let tangent = self
+            .t0
+            .multiply(w)
+            .add(self.t1.multiply(u))
+            .add(self.t2.multiply(v));
+        let tangent = if tangent.length_squared() > 0f32 {
+            tangent
+        } else {
+            dpdu
+        };
+        let tangent = tangent
+            .subtract(normal.multiply(tangent.dot(normal)))
+            .normalized();
+        let bitangent = normal.cross(tangent).multiply(self.handedness);
+        Some((
+            tangent.multiply(dpdu.length()),
+            bitangent.multiply(dpdv.length()),
+        ))
+    }
+
+    //Derivatives with respect to the barycentric coordinates of v1 and v2, given the
+    //interpolated normal before normalization
+    fn derivatives(&self, interpolated: Vector3) -> SurfaceDerivatives {
+        let length = interpolated.length();
+        let normal = interpolated.multiply(1f32 / length);
+        //Change of the normalized normal along the change of the interpolated one
+        let dndb = |d: Vector3| {
+            d.subtract(normal.multiply(normal.dot(d)))
+                .multiply(1f32 / length)
+        };
+        SurfaceDerivatives {
+            dpdb: [self.v1.subtract(self.v0), self.v2.subtract(self.v0)],
+            dndb: [
+                dndb(self.n0.subtract(self.n2)),
+                dndb(self.n1.subtract(self.n2)),
+            ],
+            duvdb: [
+                [self.uv1[0] - self.uv0[0], self.uv1[1] - self.uv0[1]],
+                [self.uv2[0] - self.uv0[0], self.uv2[1] - self.uv0[1]],
+            ],
+        }
+    }
+
+    //Texture coordinates at v0 + (v1 - v0) * u + (v2 - v0) * v
+    pub fn uv_at(&self, u: f32, v: f32) -> [f32; 2] {
+        let w = 1f32 - u - v;
+        [
+            self.uv0[0] * w + self.uv1[0] * u + self.uv2[0] * v,
+            self.uv0[1] * w + self.uv1[1] * u + self.uv2[1] * v,
+        ]
+    }
+    //Vertex color at v0 + (v1 - v0) * u + (v2 - v0) * v
+    pub fn color_at(&self, u: f32, v: f32) -> Option<Vector3> {
+        let [c0, c1, c2] = self.colors?;
+        Some(
+            c0.multiply(1f32 - u - v)
+                .add(c1.multiply(u))
+                .add(c2.multiply(v)),
+        )
+    }
+
     pub fn trace(&self, pos: &Vector3, dir: &Vector3) -> Option<(Vector3, f32, f32)> {
         let v0v1 = self.v1.subtract(self.v0);
         let v0v2 = self.v2.subtract(self.v0);
         let pvec = dir.cross(v0v2);
         let det = v0v1.dot(pvec);
+        let det = if self.material.is_double_sided() {
+            det.abs()
+        } else {
+            det
+        };
         if det < EPSILON * 4.0 {
             return None;
         }
@@ -73,6 +232,9 @@ impl Triangle {
             return None;
         }
         let t = v0v2.dot(qvec) * inv_det;
+        if t <= 0.0 {
+            return None;
+        }
         let hit = dir.multiply(t).add(*pos);
         return Some((hit, u, v));
     }
@@ -119,7 +281,8 @@ impl RayResolver for TriangleResolver {
         &self,
         pos: Vector3,
         dir: Vector3,
-        _refraction: bool,
+        differential: Option<&RayDifferential>,
+        _media: &MediumStack,
         _scene: SceneData,
     ) -> Option<RayResult> {
         match self.triangle.trace(&pos, &dir) {
@@ -127,14 +290,41 @@ impl RayResolver for TriangleResolver {
                 let n0 = self.triangle.n0.multiply(u);
                 let n1 = self.triangle.n1.multiply(v);
                 let n2 = self.triangle.n2.multiply(1.0 - u - v);
-                let normal = n0.add(n1).add(n2).normalized();
-                Some(RayResult::new(
-                    hit,
-                    self.triangle.material.color,
-                    normal,
-                    self.triangle.material.emit,
-                    self.triangle.material.t.clone(),
-                ))
+                let interpolated = n0.add(n1).add(n2);
+                let normal = interpolated.normalized();
+                let uv = self.triangle.uv_at(u, v);
+                let derivatives = self.triangle.derivatives(interpolated);
+                //Rays pass through cut out parts of the surface to whatever is behind them
+                if let Some(textures) = &self.triangle.material.textures {
+                    if textures.has_coverage() {
+                        let footprint = differential
+                            .and_then(|differential| {
+                                let normal = self.triangle.face_normal();
+                                differential.at_surface(pos, dir, hit, normal, Some(&derivatives))
+                            })
+                            .map(|surface| surface.footprint())
+                            .unwrap_or_else(Footprint::point);
+                        if textures.masked(uv, &footprint) {
+                            return None;
+                        }
+                    }
+                }
+                let material = match self.triangle.color_at(u, v) {
+                    Some(color) => self.triangle.material.clone().with_vertex_color(color),
+                    None => self.triangle.material.clone(),
+                };
+                let result = RayResult::new(hit, normal, material, self.triangle.object)
+                    .with_geometric_normal(self.triangle.face_normal())
+                    .with_uv(uv)
+                    .with_derivatives(derivatives);
+                let result = match self.triangle.tangent_frame(normal, u, v) {
+                    Some((tangent, bitangent)) => result.with_tangents(tangent, bitangent),
+                    None => result,
+                };
+                Some(match self.triangle.light {
+                    Some(light) => result.with_light(light),
+                    None => result,
+                })
             }
             None => None,
         }
@@ -1,29 +1,43 @@
 use crate::utilities::{SceneData, Vector3};
 
-use super::ray_resolver::{MaterialType, RayResolver, RayResult};
+use crate::{differentials::RayDifferential, materials::principled::Material, media::MediumStack};
+
+use super::ray_resolver::{RayResolver, RayResult};
 
 pub struct RayMarcher {
     pub max_steps: u32,
     pub max_distance: f32,
     pub epsilon: f32,
-    pub scene: fn(Vector3, bool) -> SDFResult,
+    //Receives the media the path is inside of, see `SDFResult::oriented`
+    pub scene: fn(Vector3, &MediumStack) -> SDFResult,
 }
 
 #[derive(Clone)]
 pub struct SDFResult {
     dist: f32,
-    color: Vector3,
-    emit: Vector3,
-    t: MaterialType,
+    object: u32,
+    material: Material,
 }
 
 impl SDFResult {
-    pub fn new(dist: f32, color: Vector3, emit: Vector3, t: MaterialType) -> Self {
+    pub fn new(dist: f32, object: u32, material: Material) -> Self {
         Self {
             dist,
-            color,
-            emit,
-            t,
+            object,
+            material,
+        }
+    }
+
+    //Distance as seen from a path inside the given media. The surfaces of objects the path is
+    //inside of are found from within, so their distances are negated
+    pub fn oriented(self, media: &MediumStack) -> Self {
+        if media.contains(self.object) {
+            Self {
+                dist: -self.dist,
+                ..self
+            }
+        } else {
+            self
         }
     }
 
@@ -50,27 +64,23 @@ impl SDFResult {
 }
 
 impl RayMarcher {
-    fn get_sdf(&self, p: Vector3, refraction: bool) -> SDFResult {
+    fn get_sdf(&self, p: Vector3, media: &MediumStack) -> SDFResult {
         let scene = self.scene;
-        let mut v = scene(p, refraction);
-        if refraction {
-            v.dist = -v.dist;
-        }
-        return v;
+        return scene(p, media);
     }
 
-    pub fn get_normal(&self, pos: Vector3, refraction: bool) -> Vector3 {
+    pub fn get_normal(&self, pos: Vector3, media: &MediumStack) -> Vector3 {
         let x_probe = Vector3::new(self.epsilon, 0f32, 0f32);
-        let x_delta = self.get_sdf(pos.add(x_probe), refraction).dist
-            - self.get_sdf(pos.subtract(x_probe), refraction).dist;
+        let x_delta = self.get_sdf(pos.add(x_probe), media).dist
+            - self.get_sdf(pos.subtract(x_probe), media).dist;
 
         let y_probe = Vector3::new(0f32, self.epsilon, 0f32);
-        let y_delta = self.get_sdf(pos.add(y_probe), refraction).dist
-            - self.get_sdf(pos.subtract(y_probe), refraction).dist;
+        let y_delta = self.get_sdf(pos.add(y_probe), media).dist
+            - self.get_sdf(pos.subtract(y_probe), media).dist;
 
         let z_probe = Vector3::new(0f32, 0f32, self.epsilon);
-        let z_delta = self.get_sdf(pos.add(z_probe), refraction).dist
-            - self.get_sdf(pos.subtract(z_probe), refraction).dist;
+        let z_delta = self.get_sdf(pos.add(z_probe), media).dist
+            - self.get_sdf(pos.subtract(z_probe), media).dist;
 
         Vector3::new(x_delta, y_delta, z_delta).normalized()
     }
@@ -81,7 +91,8 @@ impl RayResolver for RayMarcher {
         &self,
         pos: Vector3,
         dir: Vector3,
-        refraction: bool,
+        _: Option<&RayDifferential>,
+        media: &MediumStack,
         _: SceneData,
     ) -> Option<RayResult> {
         let mut dist = 0f32;
@@ -90,17 +101,16 @@ impl RayResolver for RayMarcher {
             if dist > self.max_distance {
                 return None;
             }
-            let sdf_value = self.get_sdf(p, refraction);
+            let sdf_value = self.get_sdf(p, media);
             dist += sdf_value.dist;
             p = pos.add(dir.multiply(dist));
             if sdf_value.dist < self.epsilon {
-                return Some(RayResult {
-                    pos: p,
-                    color: sdf_value.color,
-                    normal: self.get_normal(p, refraction),
-                    emit: sdf_value.emit,
-                    t: sdf_value.t,
-                });
+                return Some(RayResult::new(
+                    p,
+                    self.get_normal(p, media),
+                    sdf_value.material,
+                    sdf_value.object,
+                ));
             }
         }
         None
@@ -1,55 +1,132 @@
-use crate::utilities::{SceneData, Vector3};
+use crate::{
+    differentials::RayDifferential,
+    materials::principled::Material,
+    media::MediumStack,
+    textures::Footprint,
+    utilities::{SceneData, Vector3},
+};
 
 pub trait RayResolver {
+    //`differential` is the spread of the ray to its neighbouring pixels, if known, for
+    //filtering cutout masks
     fn resolve(
         &self,
         pos: Vector3,
         dir: Vector3,
-        refraction: bool,
+        differential: Option<&RayDifferential>,
+        media: &MediumStack,
         scene: SceneData,
     ) -> Option<RayResult>;
 }
 
-#[derive(Clone)]
-pub enum MaterialType {
-    Diffuse,
-    Reflective,
-    Lens,
-    Glass(f32),
-}
-
 pub struct RayResult {
     pub pos: Vector3,
-    pub color: Vector3,
+    //Shading normal, interpolated and perturbed by textures, for evaluating the material
     pub normal: Vector3,
-    pub emit: Vector3,
-    pub t: MaterialType,
+    //Normal of the actual surface, for telling its sides apart and moving rays off it
+    pub geometric_normal: Vector3,
+    pub material: Material,
+    //Identifies the closed surface that was hit, for tracking media
+    pub object: u32,
+    //Emitter that was hit, for weighting it against sampling it directly
+    pub light: Option<u32>,
+    //Interpolated texture coordinates, zero for surfaces without them
+    pub uv: [f32; 2],
+    //How the surface changes around the hit, for filtering textures
+    pub derivatives: Option<SurfaceDerivatives>,
+    //Derivatives of the position with respect to the texture coordinates, along the surface,
+    //for perturbing the normal by normal and bump maps
+    pub tangents: Option<(Vector3, Vector3)>,
+}
+
+//Derivatives of the position, normal and texture coordinates with respect to the two
+//coordinates parameterizing a surface around a hit
+#[derive(Clone, Copy)]
+pub struct SurfaceDerivatives {
+    pub dpdb: [Vector3; 2],
+    pub dndb: [Vector3; 2],
+    pub duvdb: [[f32; 2]; 2],
+}
+
+impl SurfaceDerivatives {
+    //Derivatives of the surface with its normal pointing the other way
+    pub fn flipped(self) -> Self {
+        Self {
+            dndb: [self.dndb[0].multiply(-1f32), self.dndb[1].multiply(-1f32)],
+            ..self
+        }
+    }
 }
 
 impl RayResult {
-    pub fn new(
-        pos: Vector3,
-        color: Vector3,
-        normal: Vector3,
-        emit: Vector3,
-        t: MaterialType,
-    ) -> Self {
+    pub fn new(pos: Vector3, normal: Vector3, material: Material, object: u32) -> Self {
         Self {
             pos,
-            color,
             normal,
-            emit,
-            t,
+            geometric_normal: normal,
+            material,
+            object,
+            light: None,
+            uv: [0f32; 2],
+            derivatives: None,
+            tangents: None,
+        }
+    }
+
+    pub fn with_light(self, light: u32) -> Self {
+        Self {
+            light: Some(light),
+            ..self
+        }
+    }
+
+    pub fn with_geometric_normal(self, geometric_normal: Vector3) -> Self {
+        Self {
+            geometric_normal,
+            ..self
+        }
+    }
+
+    pub fn with_uv(self, uv: [f32; 2]) -> Self {
+        Self { uv, ..self }
+    }
+
+    pub fn with_derivatives(self, derivatives: SurfaceDerivatives) -> Self {
+        Self {
+            derivatives: Some(derivatives),
+            ..self
+        }
+    }
+
+    pub fn with_tangents(self, tangent: Vector3, bitangent: Vector3) -> Self {
+        Self {
+            tangents: Some((tangent, bitangent)),
+            ..self
+        }
+    }
+
+    //Looks up the textures of the material and perturbs the normal. Resolvers leave this to
+    //the closest hit, as it is the only one that gets shaded
+    pub fn shaded(self, footprint: &Footprint) -> Self {
+        let textures = match &self.material.textures {
+            Some(textures) => textures.clone(),
+            None => return self,
+        };
+        let normal = match self.tangents {
+            Some((tangent, bitangent)) => {
+                textures.perturb_normal(self.uv, footprint, self.normal, tangent, bitangent)
+            }
+            None => self.normal,
+        };
+        let material = self.material.filtered(self.pos, self.uv, footprint);
+        Self {
+            normal,
+            material,
+            ..self
         }
     }
 
     pub fn empty() -> Self {
-        Self::new(
-            Vector3::zero(),
-            Vector3::zero(),
-            Vector3::zero(),
-            Vector3::zero(),
-            MaterialType::Diffuse,
-        )
+        Self::new(Vector3::zero(), Vector3::zero(), Material::default(), 0)
     }
 }
@@ -1,36 +1,65 @@
+use crate::differentials::RayDifferential;
+use crate::lights::Light;
+use crate::media::MediumStack;
 use crate::ray_resolvers::ray_resolver::RayResolver;
 use crate::renderers::renderer::Renderer;
+use crate::textures::Footprint;
 use crate::utilities::{SceneData, Vector3};
 
 pub struct BasicRenderer<T> {
     pub resolver: T,
+    pub lights: Vec<Light>,
 }
 
+const EPSILON: f32 = 0.0002;
+
 impl<T: RayResolver> Renderer<T> for BasicRenderer<T> {
-    fn render(&self, start: Vector3, dir: Vector3, scene: SceneData, _: u32, _: u32) -> Vector3 {
-        let result = self.resolver.resolve(start, dir, false, scene);
+    fn render(
+        &self,
+        start: Vector3,
+        dir: Vector3,
+        _: RayDifferential,
+        scene: SceneData,
+    ) -> Vector3 {
+        let result = self
+            .resolver
+            .resolve(start, dir, None, &MediumStack::new(), scene.clone())
+            .map(|v| v.shaded(&Footprint::point()));
         match result {
             None => Vector3::zero(),
             Some(v) => {
-                let lamp = Vector3::new(0.0, 0.0, -5.0);
-                let ambient = v.color.multiply(0.25);
-                let diffuse = v.color.multiply(
-                    Vector3::new(1f32, 1f32, 1f32)
-                        .subtract(v.pos)
-                        .normalized()
-                        .dot(v.normal),
-                );
-                let lamp_dir = lamp.subtract(v.pos);
-                let specular = lamp_dir.reflect(v.normal).dot(dir.multiply(-1f32));
-                let specular = if lamp_dir.dot(v.normal) < 0f32 {
-                    0f32
-                } else {
-                    specular
-                };
-                let specular = if specular < 0f32 { 0f32 } else { specular };
-                let specular = specular.powf(5f32);
-                let specular = Vector3::from_single(specular);
-                ambient.add(diffuse).add(specular).add(v.emit)
+                let color = v.material.base_color;
+                let ambient = color.multiply(0.25);
+                let p = v.pos.add(v.geometric_normal.multiply(EPSILON));
+                let lit = self
+                    .lights
+                    .iter()
+                    .filter_map(|light| light.sample(p))
+                    .filter(|sample| {
+                        //Shadows
+                        match self.resolver.resolve(
+                            p,
+                            sample.dir,
+                            None,
+                            &MediumStack::new(),
+                            scene.clone(),
+                        ) {
+                            Some(hit) => hit.pos.subtract(p).length() >= sample.distance,
+                            None => true,
+                        }
+                    })
+                    .fold(Vector3::zero(), |sum, sample| {
+                        let diffuse = color.multiply(sample.dir.dot(v.normal).max(0f32));
+                        let specular = if sample.dir.dot(v.normal) < 0f32 {
+                            0f32
+                        } else {
+                            sample.dir.reflect(v.normal).dot(dir.multiply(-1f32))
+                        };
+                        let specular = specular.max(0f32).powf(5f32);
+                        let specular = Vector3::from_single(specular);
+                        sum.add(diffuse.add(specular).comp_multiply(sample.radiance))
+                    });
+                ambient.add(lit).add(v.material.emitted())
             }
         }
     }
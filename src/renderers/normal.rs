@@ -1,5 +1,8 @@
 use crate::{
+    differentials::RayDifferential,
+    media::MediumStack,
     ray_resolvers::ray_resolver::RayResolver,
+    textures::Footprint,
     utilities::{SceneData, Vector3},
 };
 
@@ -13,9 +16,18 @@ where
 }
 
 impl<T: RayResolver> Renderer<T> for NormalRenderer<T> {
-    fn render(&self, start: Vector3, end: Vector3, scene: SceneData, _: u32, _: u32) -> Vector3 {
-        let result = match self.resolver.resolve(start, end, false, scene) {
-            Some(a) => a,
+    fn render(
+        &self,
+        start: Vector3,
+        end: Vector3,
+        _: RayDifferential,
+        scene: SceneData,
+    ) -> Vector3 {
+        let result = match self
+            .resolver
+            .resolve(start, end, None, &MediumStack::new(), scene)
+        {
+            Some(a) => a.shaded(&Footprint::point()),
             None => return Vector3::zero(),
         };
         result.normal
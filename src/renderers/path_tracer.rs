@@ -1,7 +1,13 @@
-use std::{f32::consts::PI, usize};
+use std::{cmp::Ordering, f32::consts::PI, usize};
 
-use crate::ray_resolvers::ray_resolver::{MaterialType, RayResolver};
+use crate::differentials::RayDifferential;
+use crate::lights::{light_tree::Emitters, Light, LightSample};
+use crate::materials::{principled::Material, BsdfSample};
+use crate::media::{fog, phase, volume::Volume, MediumStack};
+use crate::ray_resolvers::ray_resolver::{RayResolver, RayResult};
 use crate::renderers::renderer::Renderer;
+use crate::spectrum;
+use crate::textures::Footprint;
 use crate::utilities::{SceneData, Vector3};
 use rand_distr::{Distribution, Uniform};
 
@@ -17,19 +23,343 @@ pub struct PathTracer<T> {
     pub dof: bool,
     pub skybox: Vec<Vec<[f32; 4]>>,
     pub skybox_size: (usize, usize),
+    //Maximum component of light reaching the camera after one bounce
+    pub clamp_direct: Option<f32>,
+    //Maximum component of light reaching the camera after two or more bounces
+    pub clamp_indirect: Option<f32>,
+    //Roughness given to specular surfaces once a path has hit a diffuse one
+    pub regularization: Option<f32>,
+    //Trace wavelengths instead of RGB, enabling dispersion
+    pub spectral: bool,
+    //Maximum number of scattering events inside objects' volumes, which don't count as bounces
+    pub volume_steps: u32,
+    //Bounded media with varying density, such as clouds, smoke and fire
+    pub volumes: Vec<Volume>,
+    pub lights: Vec<Light>,
+    //Emissive triangles of the scene, sampled through their light tree
+    pub emitters: Emitters,
 }
 
-fn find_outgoing(incoming: Vector3, normal: Vector3, material: MaterialType) -> Vector3 {
-    match material {
-        MaterialType::Diffuse => Vector3::random_on_hemisphere(normal),
-        MaterialType::Reflective => incoming.reflect(normal),
-        MaterialType::Lens => Vector3::zero().subtract(normal),
-        MaterialType::Glass(ior) => incoming.refract(normal, ior),
+//Source of light picked for next event estimation
+enum Source<'a> {
+    Light(&'a Light),
+    Emitters,
+}
+
+const MAX_FALSE_HITS: u32 = 16;
+
+fn find_outgoing(
+    incoming: Vector3,
+    normal: Vector3,
+    material: &Material,
+    eta: f32,
+) -> Option<BsdfSample> {
+    material.sample(incoming.multiply(-1f32), normal, eta)
+}
+
+//Converts a color to spectral values if rendering spectrally
+fn spectral(color: Vector3, wavelengths: Option<Vector3>) -> Vector3 {
+    match wavelengths {
+        Some(wavelengths) => spectrum::rgb_to_spectrum(color, wavelengths),
+        None => color,
+    }
+}
+
+//Weight of a strategy with probability `a` against one with probability `b`
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    if a <= 0f32 {
+        return 0f32;
+    }
+    a * a / (a * a + b * b)
+}
+
+fn clamp_radiance(color: Vector3, limit: Option<f32>) -> Vector3 {
+    match limit {
+        Some(limit) if color.largest_component_value() > limit => {
+            color.multiply(limit / color.largest_component_value())
+        }
+        _ => color,
     }
 }
 
 impl<T: RayResolver> PathTracer<T> {
-    fn render_sample(&self, start: &Vector3, dir: &Vector3, scene: &SceneData) -> (Vector3, u32) {
+    //Clamps light picked up on the given bounce of a path
+    fn clamp(&self, color: Vector3, bounce: u32) -> Vector3 {
+        match bounce {
+            0 => color,
+            1 => clamp_radiance(color, self.clamp_direct),
+            _ => clamp_radiance(color, self.clamp_indirect),
+        }
+    }
+
+    //Transmittance of fog and volumes along a ray
+    fn transmittance(&self, p: Vector3, dir: Vector3, distance: f32, scene: &SceneData) -> f32 {
+        self.volumes
+            .iter()
+            .fold(fog::transmittance(scene, p, dir, distance), |t, v| {
+                t * v.transmittance(p, dir, distance)
+            })
+    }
+
+    //Fraction of light travelling along a ray that reaches `distance`. Surfaces block it entirely
+    fn visibility(&self, p: Vector3, dir: Vector3, distance: f32, scene: &SceneData) -> f32 {
+        if let Some(hit) = self
+            .resolver
+            .resolve(p, dir, None, &MediumStack::new(), scene.clone())
+        {
+            if hit.pos.subtract(p).length() < distance - self.epsilon {
+                return 0f32;
+            }
+        }
+        self.transmittance(p, dir, distance, scene)
+    }
+
+    //Every light counts as a source, as do all emitters together
+    fn sources(&self) -> usize {
+        self.lights.len() + if self.emitters.is_empty() { 0 } else { 1 }
+    }
+
+    //Picks a source uniformly, along with the reciprocal of the probability of picking it
+    fn pick_light(&self) -> Option<(Source<'_>, f32)> {
+        let count = self.sources();
+        if count == 0 {
+            return None;
+        }
+        let index = ((rand::random::<f32>() * count as f32) as usize).min(count - 1);
+        let source = match self.lights.get(index) {
+            Some(light) => Source::Light(light),
+            None => Source::Emitters,
+        };
+        Some((source, count as f32))
+    }
+
+    //Direction towards the source, radiance arriving from it at `p` divided by the probability
+    //of sampling it, and that probability. Emitters are picked for a surface facing `normal`
+    fn light_from(
+        &self,
+        source: &Source,
+        p: Vector3,
+        normal: Option<Vector3>,
+        scene: &SceneData,
+        wavelengths: Option<Vector3>,
+    ) -> Option<(Vector3, Vector3, f32)> {
+        let sample: LightSample = match source {
+            Source::Light(light) => light.sample(p)?,
+            Source::Emitters => self.emitters.sample(p, normal)?,
+        };
+        let visibility = self.visibility(p, sample.dir, sample.distance, scene);
+        if visibility <= 0f32 {
+            return None;
+        }
+        let radiance = spectral(sample.radiance, wavelengths).multiply(visibility);
+        Some((sample.dir, radiance, sample.pdf))
+    }
+
+    //Next event estimation towards a randomly picked source, weighted against a scattering
+    //function sampling the same direction with probability `pdf`
+    fn sample_light(
+        &self,
+        p: Vector3,
+        normal: Option<Vector3>,
+        scene: &SceneData,
+        wavelengths: Option<Vector3>,
+        pdf: impl Fn(Vector3) -> f32,
+    ) -> Option<(Vector3, Vector3)> {
+        let (source, count) = self.pick_light()?;
+        let (dir, radiance, light_pdf) = self.light_from(&source, p, normal, scene, wavelengths)?;
+        //Lights without an extent can't be hit by chance
+        let weight = if light_pdf > 0f32 {
+            power_heuristic(light_pdf / count, pdf(dir))
+        } else {
+            1f32
+        };
+        Some((dir, radiance.multiply(count * weight)))
+    }
+
+    //Weight of light from an emitter hit at `point`, when the direction towards it was sampled
+    //from `start` with probability `pdf`
+    fn emitter_weight(
+        &self,
+        start: Vector3,
+        normal: Option<Vector3>,
+        pdf: Option<f32>,
+        emitter: Option<u32>,
+        point: Vector3,
+    ) -> f32 {
+        match (pdf, emitter) {
+            (Some(pdf), Some(emitter)) => {
+                let light_pdf = self.emitters.pdf(start, normal, emitter, point);
+                power_heuristic(pdf, light_pdf / self.sources() as f32)
+            }
+            _ => 1f32,
+        }
+    }
+
+    //Radiance of lights a ray hits before `distance`, weighted against next event estimation
+    //if the direction was sampled with probability `pdf`
+    fn light_hits(
+        &self,
+        start: Vector3,
+        dir: Vector3,
+        distance: f32,
+        pdf: Option<f32>,
+        wavelengths: Option<Vector3>,
+    ) -> Vector3 {
+        let sources = self.sources() as f32;
+        self.lights
+            .iter()
+            .filter_map(|light| {
+                let (t, radiance) = light.intersect(start, dir)?;
+                if t > distance {
+                    return None;
+                }
+                let weight = match pdf {
+                    Some(pdf) => power_heuristic(pdf, light.pdf(start, dir) / sources),
+                    None => 1f32,
+                };
+                Some(spectral(radiance, wavelengths).multiply(weight))
+            })
+            .fold(Vector3::zero(), |sum, radiance| sum.add(radiance))
+    }
+
+    //Light scattered by fog into a ray before `distance`. The scattering point is sampled
+    //equiangularly around a light, which concentrates samples where it is the brightest
+    fn fog_inscatter(
+        &self,
+        start: Vector3,
+        dir: Vector3,
+        distance: f32,
+        scene: &SceneData,
+        wavelengths: Option<Vector3>,
+    ) -> Vector3 {
+        let (source, count) = match self.pick_light() {
+            Some(source) => source,
+            None => return Vector3::zero(),
+        };
+        let (t, weight) = match source {
+            Source::Light(light) if !light.is_directional() => {
+                //Closest point of the ray to the light
+                let delta = light.position.subtract(start).dot(dir);
+                let d = start
+                    .add(dir.multiply(delta))
+                    .subtract(light.position)
+                    .length()
+                    .max(1e-4);
+                let theta_a = (-delta / d).atan();
+                let theta_b = ((distance - delta) / d).atan();
+                if theta_b <= theta_a {
+                    return Vector3::zero();
+                }
+                let theta = theta_a + rand::random::<f32>() * (theta_b - theta_a);
+                let offset = d * theta.tan();
+                let t = delta + offset;
+                let pdf = d / ((theta_b - theta_a) * (d * d + offset * offset));
+                let p = start.add(dir.multiply(t));
+                let scattering = fog::density(scene, p) / scene.fog_amount;
+                let transmittance = self.transmittance(start, dir, t, scene);
+                (t, scattering * transmittance / pdf)
+            }
+            _ => {
+                //Directional lights have no closest point and emitters are only picked once
+                //the point is known, so the scattering point follows the density of the fog
+                //instead, cancelling out its transmittance
+                let t = fog::sample_distance(scene, start, dir);
+                if t >= distance {
+                    return Vector3::zero();
+                }
+                let transmittance = self
+                    .volumes
+                    .iter()
+                    .fold(1f32, |tr, v| tr * v.transmittance(start, dir, t));
+                (t, transmittance)
+            }
+        };
+        let p = start.add(dir.multiply(t));
+        let (wi, radiance, _) = match self.light_from(&source, p, None, scene, wavelengths) {
+            Some(light) => light,
+            None => return Vector3::zero(),
+        };
+        let phase = phase::henyey_greenstein(dir.dot(wi), scene.fog_anisotropy);
+        spectral(scene.fog_albedo, wavelengths)
+            .comp_multiply(radiance)
+            .multiply(phase * weight * count)
+    }
+
+    //Finds the next surface that is an actual interface, passing through
+    //surfaces of media that are overridden by a higher priority one. Returns None if
+    //the path gets stuck passing through surfaces, rather than escaping the scene
+    fn trace(
+        &self,
+        start: Vector3,
+        dir: Vector3,
+        media: &mut MediumStack,
+        scene: &SceneData,
+        wavelengths: Option<Vector3>,
+        differential: Option<&RayDifferential>,
+    ) -> Option<Option<RayResult>> {
+        let mut pos = start;
+        for _ in 0..MAX_FALSE_HITS {
+            let moved = differential.map(|d| d.advanced(pos.subtract(start).length()));
+            let ray = match self
+                .resolver
+                .resolve(pos, dir, moved.as_ref(), media, scene.clone())
+            {
+                Some(ray) => ray,
+                None => return Some(None),
+            };
+            //Media are tracked as seen by the wavelengths, before the surface is textured
+            let interior = match wavelengths {
+                Some(wavelengths) => ray.material.to_spectral(wavelengths).interior(),
+                None => ray.material.interior(),
+            };
+            let medium = match interior {
+                Some(medium) if media.is_false_hit(ray.object, &medium) => medium,
+                _ => return Some(Some(self.shade(ray, start, dir, wavelengths, differential))),
+            };
+            media.cross(ray.object, medium);
+            pos = ray.pos.add(dir.multiply(self.epsilon * 2f32));
+        }
+        None
+    }
+
+    //Looks up the textures of an interface, averaged over the footprint of the pixel the path
+    //started from
+    fn shade(
+        &self,
+        ray: RayResult,
+        start: Vector3,
+        dir: Vector3,
+        wavelengths: Option<Vector3>,
+        differential: Option<&RayDifferential>,
+    ) -> RayResult {
+        let footprint = match (differential, &ray.material.textures) {
+            (Some(differential), Some(_)) => differential
+                .at_surface(
+                    start,
+                    dir,
+                    ray.pos,
+                    ray.geometric_normal,
+                    ray.derivatives.as_ref(),
+                )
+                .map(|surface| surface.footprint()),
+            _ => None,
+        };
+        let mut ray = ray.shaded(&footprint.unwrap_or_else(Footprint::point));
+        if let Some(wavelengths) = wavelengths {
+            ray.material = ray.material.to_spectral(wavelengths);
+        }
+        ray
+    }
+
+    //Returns RGB radiance, or radiance at each wavelength if given
+    fn render_sample(
+        &self,
+        start: &Vector3,
+        dir: &Vector3,
+        differential: RayDifferential,
+        scene: &SceneData,
+        wavelengths: Option<Vector3>,
+    ) -> (Vector3, u32) {
         let mut start = *start;
         let mut dir = *dir;
 
@@ -46,57 +376,261 @@ impl<T: RayResolver> PathTracer<T> {
 
         let mut emit = Vector3::zero();
         let mut rad = Vector3::new(1f32, 1f32, 1f32);
-        let mut rng = rand::thread_rng();
-        let mut refraction = false;
-        for i in 0..self.bounces {
-            let random = Uniform::new(0f32, 1f32).sample(&mut rng);
-            let dust_dist = -random.ln() * scene.fog_amount;
-            match { self.resolver.resolve(start, dir, refraction, scene.clone()) } {
-                None => {
-                    //return (emit, i)
-                    if scene.fog {
-                        start = start.add(dir.multiply(dust_dist));
-                        dir = Vector3::random_on_sphere();
-                    } else {
-                        //Render skybox
-                        let color = {
-                            let x = ((dir.x.atan2(dir.z) / PI + 1.0)
-                                * 0.5
-                                * (self.skybox_size.0 - 1) as f32)
-                                as usize;
-                            let y = (((-dir.y).asin() / (PI / 2.0) + 1.0)
-                                * 0.5
-                                * (self.skybox_size.1 - 1) as f32)
-                                as usize;
-                            let x = x.clamp(0, self.skybox_size.0 - 1);
-                            let y = y.clamp(0, self.skybox_size.1 - 1);
-                            let v = self.skybox[y][x];
-                            Vector3::new(v[0], v[1], v[2])
-                        };
-                        let emit = emit.add(rad.comp_multiply(color));
-                        return (emit, i);
+        let mut media = MediumStack::new();
+        let mut dispersed = false;
+        let mut diffuse_bounce = false;
+        //Probability the last bounce sampled its direction with, unless it was specular
+        let mut last_pdf = None;
+        //Surface the last bounce happened on, which picking emitters depends on
+        let mut last_normal = None;
+        //Light reached right after scattering in fog is already covered by `fog_inscatter`
+        let mut fog_scatter = false;
+        //Footprint of the pixel, which is only tracked through specular bounces
+        let mut differential = Some(differential);
+        let mut i = 0;
+        let mut steps = 0;
+        while i < self.bounces {
+            let hit = match self.trace(
+                start,
+                dir,
+                &mut media,
+                scene,
+                wavelengths,
+                differential.as_ref(),
+            ) {
+                Some(hit) => hit,
+                None => break,
+            };
+            //Volumes and fog extend indefinitely past the last surface
+            let distance = match &hit {
+                Some(ray) => ray.pos.subtract(start).length(),
+                None => f32::INFINITY,
+            };
+            //Fog only fills the space outside of objects
+            let fog_distance = if media.is_empty() {
+                fog::sample_distance(scene, start, dir)
+            } else {
+                f32::INFINITY
+            };
+            let collision = self
+                .volumes
+                .iter()
+                .filter_map(|v| {
+                    Some((
+                        v.sample_collision(start, dir, distance.min(fog_distance))?,
+                        v,
+                    ))
+                })
+                .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+            //The path continues from a volume collision, so fog past it is accounted for there
+            if media.is_empty() && scene.fog {
+                let end = collision.map(|(t, _)| t).unwrap_or(distance);
+                let inscatter = self.fog_inscatter(start, dir, end, scene, wavelengths);
+                emit = emit.add(self.clamp(rad.comp_multiply(inscatter), i + 1));
+            }
+            let after_fog = fog_scatter;
+            fog_scatter = false;
+            if media.is_empty() && !after_fog {
+                let end = collision
+                    .map(|(t, _)| t)
+                    .unwrap_or(distance)
+                    .min(fog_distance);
+                let lights = self.light_hits(start, dir, end, last_pdf, wavelengths);
+                emit = emit.add(self.clamp(rad.comp_multiply(lights), i));
+            }
+            //Paths leaving an object through a gap in its surface leave its medium as well
+            if !media.is_empty() && hit.is_some() {
+                let distance = collision.map(|(t, _)| t).unwrap_or(distance);
+                let interaction = media.current().sample_interaction(distance, rad);
+                rad = rad.comp_multiply(interaction.weight);
+                if interaction.scattered {
+                    steps += 1;
+                    if steps > self.volume_steps {
+                        break;
                     }
+                    start = start.add(dir.multiply(interaction.distance));
+                    dir = Vector3::random_on_sphere();
+                    last_pdf = None;
+                    last_normal = None;
+                    differential = None;
+                    continue;
+                }
+            }
+            if let Some((t, volume)) = collision {
+                let albedo = spectral(volume.albedo, wavelengths);
+                let emission = spectral(volume.emission, wavelengths);
+                //The absorbed part of the collision emits, the rest scatters
+                let absorbed = Vector3::from_single(1f32).subtract(albedo);
+                emit = emit.add(self.clamp(rad.comp_multiply(absorbed).comp_multiply(emission), i));
+                rad = rad.comp_multiply(albedo);
+                let p = start.add(dir.multiply(t));
+                //Volumes scatter isotropically
+                let isotropic = 1f32 / (4f32 * PI);
+                if let Some((_, radiance)) =
+                    self.sample_light(p, None, scene, wavelengths, |_| isotropic)
+                {
+                    let light = rad.comp_multiply(radiance).multiply(isotropic);
+                    emit = emit.add(self.clamp(light, i + 1));
+                }
+                steps += 1;
+                if steps > self.volume_steps || rad.largest_component_value() <= 0f32 {
+                    break;
+                }
+                start = p;
+                dir = Vector3::random_on_sphere();
+                last_pdf = Some(isotropic);
+                last_normal = None;
+                differential = None;
+                continue;
+            }
+            if fog_distance < distance {
+                rad = rad.comp_multiply(spectral(scene.fog_albedo, wavelengths));
+                start = start.add(dir.multiply(fog_distance));
+                dir = phase::sample_henyey_greenstein(dir, scene.fog_anisotropy);
+                fog_scatter = true;
+                differential = None;
+                i += 1;
+                continue;
+            }
+            match hit {
+                None => {
+                    //Render skybox
+                    let color = {
+                        let x = ((dir.x.atan2(dir.z) / PI + 1.0)
+                            * 0.5
+                            * (self.skybox_size.0 - 1) as f32)
+                            as usize;
+                        let y = (((-dir.y).asin() / (PI / 2.0) + 1.0)
+                            * 0.5
+                            * (self.skybox_size.1 - 1) as f32)
+                            as usize;
+                        let x = x.clamp(0, self.skybox_size.0 - 1);
+                        let y = y.clamp(0, self.skybox_size.1 - 1);
+                        let v = self.skybox[y][x];
+                        Vector3::new(v[0], v[1], v[2])
+                    };
+                    let color = spectral(color, wavelengths);
+                    let emit = emit.add(self.clamp(rad.comp_multiply(color), i));
+                    return (emit, i);
                 }
                 Some(ray) => {
-                    if scene.fog && ray.pos.subtract(start).length() > dust_dist {
-                        start = start.add(dir.multiply(dust_dist));
-                        dir = Vector3::random_on_sphere();
-                        continue;
+                    //Emitters were already sampled from fog
+                    let weight = if after_fog && ray.light.is_some() {
+                        0f32
+                    } else {
+                        self.emitter_weight(start, last_normal, last_pdf, ray.light, ray.pos)
+                    };
+                    let emitted = ray.material.emitted().multiply(weight);
+                    emit = emit.add(self.clamp(rad.comp_multiply(emitted), i));
+                    let material = match self.regularization {
+                        Some(roughness) if diffuse_bounce => ray.material.regularized(roughness),
+                        _ => ray.material,
+                    };
+                    let incoming = ray.pos.subtract(start).normalized();
+                    //Normals on the side the ray arrived from. The sides are told apart by the
+                    //actual surface, the shading normal only goes into the material
+                    let flipped = incoming.dot(ray.geometric_normal) > 0f32;
+                    let (normal, geometric_normal) = if flipped {
+                        (
+                            ray.normal.multiply(-1f32),
+                            ray.geometric_normal.multiply(-1f32),
+                        )
+                    } else {
+                        (ray.normal, ray.geometric_normal)
+                    };
+                    let interior = material.interior();
+                    let eta = match &interior {
+                        Some(medium) => media.eta(ray.object, medium),
+                        None => 1f32,
+                    };
+                    let wo = incoming.multiply(-1f32);
+                    //Dispersive interfaces are evaluated with the IOR of the hero wavelength,
+                    //for reflection as well as refraction, so only it carries on
+                    if wavelengths.is_some() && material.dispersion.is_some() && !dispersed {
+                        rad = Vector3::new(rad.x * 3f32, 0f32, 0f32);
+                        dispersed = true;
                     }
-                    emit = emit.add(rad.comp_multiply(ray.emit));
-                    dir = find_outgoing(ray.pos.subtract(start).normalized(), ray.normal, ray.t);
-                    rad = rad.comp_multiply(ray.color.multiply(ray.normal.dot(dir)));
-                    start = ray.pos.add(ray.normal.multiply(self.epsilon * 2f32));
+                    //A random walk ends at the first surface it reaches
+                    let exiting = material.subsurface > 0f32 && media.contains(ray.object);
+                    let outside = if exiting {
+                        ray.pos
+                            .subtract(geometric_normal.multiply(self.epsilon * 2f32))
+                    } else {
+                        ray.pos.add(geometric_normal.multiply(self.epsilon * 2f32))
+                    };
+                    let pdf = |wi: Vector3| {
+                        if exiting {
+                            wi.dot(normal.multiply(-1f32)).max(0f32) / PI
+                        } else {
+                            material.pdf(wo, wi, normal, eta)
+                        }
+                    };
+                    if let Some((wi, radiance)) =
+                        self.sample_light(outside, Some(normal), scene, wavelengths, pdf)
+                    {
+                        let f = if exiting {
+                            material.eval_subsurface_exit(wo, wi, normal)
+                        } else {
+                            material.eval(wo, wi, normal, eta)
+                        };
+                        let light = rad.comp_multiply(f).comp_multiply(radiance);
+                        emit = emit.add(self.clamp(light, i + 1));
+                    }
+                    let sample = if exiting {
+                        material.sample_subsurface_exit(wo, normal)
+                    } else {
+                        match find_outgoing(incoming, normal, &material, eta) {
+                            Some(sample) => sample,
+                            None => break,
+                        }
+                    };
+                    diffuse_bounce = diffuse_bounce || !sample.specular;
+                    last_pdf = if sample.specular {
+                        None
+                    } else {
+                        Some(sample.pdf)
+                    };
+                    last_normal = Some(normal);
+                    differential = match differential {
+                        Some(differential) if sample.specular && !exiting => {
+                            let derivatives =
+                                ray.derivatives
+                                    .map(|d| if flipped { d.flipped() } else { d });
+                            differential
+                                .at_surface(
+                                    start,
+                                    incoming,
+                                    ray.pos,
+                                    geometric_normal,
+                                    derivatives.as_ref(),
+                                )
+                                .map(|surface| {
+                                    if sample.dir.dot(geometric_normal) < 0f32 {
+                                        differential
+                                            .refract(incoming, sample.dir, normal, eta, &surface)
+                                    } else {
+                                        differential.reflect(incoming, normal, &surface)
+                                    }
+                                })
+                        }
+                        _ => None,
+                    };
+                    dir = sample.dir;
+                    rad = rad.comp_multiply(sample.weight);
+                    start = ray.pos.add(geometric_normal.multiply(self.epsilon * 2f32));
                     if rad.x == 0f32 && rad.y == 0f32 && rad.z == 0f32 {
                         break;
                     }
-                    if dir.dot(ray.normal.clone()) < 0f32 {
+                    if dir.dot(geometric_normal) < 0f32 {
                         //Refraction
-                        refraction = !refraction;
-                        start = start.subtract(ray.normal.multiply(4f32 * self.epsilon))
+                        if let Some(medium) = interior {
+                            media.cross(ray.object, medium);
+                        }
+                        start = start.subtract(geometric_normal.multiply(4f32 * self.epsilon))
                     }
                 }
             }
+            i += 1;
         }
         (emit, self.bounces - 1)
     }
@@ -107,22 +641,22 @@ impl<T: RayResolver> Renderer<T> for PathTracer<T> {
         &self,
         start: Vector3,
         dir: Vector3,
+        differential: RayDifferential,
         scene: SceneData,
-        _width: u32,
-        height: u32,
     ) -> Vector3 {
-        let mut o = Vector3::zero();
-        let mut rng = rand::thread_rng();
-        let distr = Uniform::new(-0.5 / height as f32, 0.5 / height as f32);
-        for _ in 0..self.samples {
-            let aa_jitter = Vector3::new(distr.sample(&mut rng), distr.sample(&mut rng), 0f32);
-            let dir_aa = dir.clone().add(aa_jitter);
-            let (c, _) = self.render_sample(&start, &dir_aa, &scene);
-            o = o.add(c);
+        if self.spectral {
+            let wavelengths = spectrum::sample_wavelengths();
+            let (c, _) = self.render_sample(&start, &dir, differential, &scene, Some(wavelengths));
+            spectrum::spectrum_to_rgb(c, wavelengths)
+        } else {
+            let (c, _) = self.render_sample(&start, &dir, differential, &scene, None);
+            c
         }
-        o.multiply(1f32 / (self.samples as f32))
     }
     fn needs_toneing() -> bool {
         true
     }
+    fn samples(&self) -> u32 {
+        self.samples
+    }
 }
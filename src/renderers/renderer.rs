@@ -1,4 +1,5 @@
 use crate::{
+    differentials::RayDifferential,
     ray_resolvers::ray_resolver::RayResolver,
     utilities::{SceneData, Vector3},
 };
@@ -8,9 +9,12 @@ pub trait Renderer<T: RayResolver> {
         &self,
         start: Vector3,
         end: Vector3,
+        differential: RayDifferential,
         scene: SceneData,
-        width: u32,
-        height: u32,
     ) -> Vector3;
     fn needs_toneing() -> bool;
+    //Number of camera samples taken per pixel
+    fn samples(&self) -> u32 {
+        1
+    }
 }
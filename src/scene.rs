@@ -1,41 +1,75 @@
 use crate::ray_resolvers::bvh::aabb::AABBRayResolver;
 use crate::{
+    lights::light_tree::Emitters,
+    lights::Light,
+    materials::principled::Material,
+    media::{Medium, MediumStack},
     ray_resolvers::{
-        bvh::generate_bvh_from_file, ray_marcher::SDFResult, ray_resolver::MaterialType,
+        bvh::{generate_bvh_with_lights, gltf_scene::GltfCamera, scene_from_file, ImportOptions},
+        ray_marcher::SDFResult,
     },
     utilities::Vector3,
 };
 
-fn raymarcher_scene(p: Vector3, refraction: bool) -> SDFResult {
+#[allow(dead_code)]
+fn raymarcher_scene(p: Vector3, media: &MediumStack) -> SDFResult {
     let sphere1 = SDFResult::new(
         SDFResult::sphere_dist(p, Vector3::new(0f32, 0.0f32, 4f32), 1.5),
-        Vector3::from_single(1.0),
-        Vector3::zero(),
-        MaterialType::Diffuse,
-    );
+        0,
+        Material::diffuse(Vector3::from_single(1.0)),
+    )
+    .oriented(media);
     let sphere2 = SDFResult::new(
         SDFResult::sphere_dist(p, Vector3::new(-3f32, 0.0f32, 4f32), 1.5),
-        Vector3::from_single(1.0),
-        Vector3::zero(),
-        MaterialType::Reflective,
-    );
+        1,
+        Material::metal(Vector3::from_single(1.0), 0f32),
+    )
+    .oriented(media);
     let sphere3 = SDFResult::new(
         SDFResult::sphere_dist(p, Vector3::new(3f32, 0.0f32, 4f32), 1.5),
-        Vector3::from_int(0xebbdb9).srgb(),
-        Vector3::zero(),
-        MaterialType::Glass(if refraction {
-            1.52 / 1.000293
-        } else {
-            1.000293 / 1.52
-        }),
-    );
+        2,
+        Material {
+            absorption: Medium::absorption_from_color(Vector3::from_int(0xebbdb9).srgb(), 3.0),
+            ..Material::glass(Vector3::from_single(1.0), 1.52, 0f32)
+        },
+    )
+    .oriented(media);
 
     sphere1.union(sphere2).union(sphere3)
 }
 
-pub fn get_resolver() -> AABBRayResolver {
+const SCENE_FILE: &str = "teapot.obj";
+
+//Contents of the scene file. The lights are shared by every renderer
+pub struct Scene {
+    pub resolver: AABBRayResolver,
+    pub emitters: Emitters,
+    pub lights: Vec<Light>,
+    //Camera placed in the file, if any
+    pub camera: Option<GltfCamera>,
+}
+
+pub fn get_scene() -> Scene {
     println!("Building BVH");
-    let r = generate_bvh_from_file("teapot.obj").unwrap();
+    let scene = scene_from_file(SCENE_FILE, &ImportOptions::default()).unwrap();
+    let (resolver, emitters) = generate_bvh_with_lights(scene.triangles).unwrap();
     println!("BVH done!");
-    r
+    Scene {
+        resolver,
+        emitters,
+        lights: scene.lights,
+        camera: scene.cameras.into_iter().next(),
+    }
+}
+
+//The basic renderer sees nothing but lights, so it falls back to the lamp it used to have
+pub fn get_basic_lights(lights: &[Light]) -> Vec<Light> {
+    if !lights.is_empty() {
+        return lights.to_vec();
+    }
+    vec![Light::point(
+        Vector3::new(0.0, 0.0, -5.0),
+        Vector3::from_single(1.0),
+        25.0,
+    )]
 }
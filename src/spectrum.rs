@@ -0,0 +1,113 @@
+use crate::utilities::{Matrix3, Vector3};
+
+//Spectral rendering helpers. A path carries three wavelengths in nanometers packed in a
+//Vector3: a uniformly sampled hero wavelength and two others spaced evenly from it.
+//Spectral values at those wavelengths are packed the same way.
+
+pub const LAMBDA_MIN: f32 = 380f32;
+pub const LAMBDA_MAX: f32 = 720f32;
+
+//Integral of the CIE Y matching function
+const CIE_Y_INTEGRAL: f32 = 106.856_895;
+
+const SMITS_BINS: usize = 10;
+const SMITS_WHITE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f32; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f32; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f32; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f32; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f32; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+pub fn sample_wavelengths() -> Vector3 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = rand::random::<f32>() * range;
+    let rotate = |i: f32| LAMBDA_MIN + (hero + i * range / 3f32) % range;
+    Vector3::new(rotate(0f32), rotate(1f32), rotate(2f32))
+}
+
+//Smits' RGB to spectrum conversion, evaluated at a single wavelength
+fn smits(rgb: Vector3, lambda: f32) -> f32 {
+    let bin = ((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * SMITS_BINS as f32) as usize;
+    let bin = bin.min(SMITS_BINS - 1);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        let v = r * SMITS_WHITE[bin];
+        if g <= b {
+            v + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            v + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        let v = g * SMITS_WHITE[bin];
+        if r <= b {
+            v + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            v + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        let v = b * SMITS_WHITE[bin];
+        if r <= g {
+            v + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            v + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
+
+pub fn rgb_to_spectrum(rgb: Vector3, wavelengths: Vector3) -> Vector3 {
+    Vector3::new(
+        smits(rgb, wavelengths.x),
+        smits(rgb, wavelengths.y),
+        smits(rgb, wavelengths.z),
+    )
+}
+
+fn lobe(lambda: f32, mean: f32, left: f32, right: f32) -> f32 {
+    let t = (lambda - mean) / if lambda < mean { left } else { right };
+    (-0.5 * t * t).exp()
+}
+
+//Analytic fit of the CIE 1931 matching functions (Wyman et al. 2013)
+pub fn cie_xyz(lambda: f32) -> Vector3 {
+    let x = 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(lambda, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8);
+    Vector3::new(x, y, z)
+}
+
+fn xyz_to_srgb(xyz: Vector3) -> Vector3 {
+    Matrix3::new(
+        Vector3::new(3.240_454, -0.969_266, 0.055_643),
+        Vector3::new(-1.537_139, 1.876_011, -0.204_026),
+        Vector3::new(-0.498_531, 0.041_556, 1.057_225),
+    )
+    .multiply(xyz)
+}
+
+//Converts spectral radiance samples to linear sRGB, such that a constant spectrum
+//of 1 is white
+pub fn spectrum_to_rgb(values: Vector3, wavelengths: Vector3) -> Vector3 {
+    let xyz = cie_xyz(wavelengths.x)
+        .multiply(values.x)
+        .add(cie_xyz(wavelengths.y).multiply(values.y))
+        .add(cie_xyz(wavelengths.z).multiply(values.z))
+        .multiply((LAMBDA_MAX - LAMBDA_MIN) / (3f32 * CIE_Y_INTEGRAL));
+    let white = xyz_to_srgb(Vector3::from_single(1f32));
+    xyz_to_srgb(xyz).comp_multiply(white.reciprocal())
+}
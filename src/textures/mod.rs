@@ -0,0 +1,452 @@
+use std::{path::Path, sync::Arc};
+
+use crate::{error::Error, utilities::Vector3};
+
+use self::procedural::Texture;
+
+pub mod procedural;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    //Maps a texel coordinate into [0, size)
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i >= size {
+                    2 * size - 1 - i
+                } else {
+                    i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+//Single resolution of an image
+pub struct MipLevel {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vector3>,
+    //Alpha channel, if the image has one
+    pub alpha: Option<Vec<f32>>,
+}
+
+impl MipLevel {
+    //Level of half the resolution, averaging blocks of 2x2 texels
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let block = |x: usize, y: usize| {
+            let x0 = (x * 2).min(self.width - 1);
+            let y0 = (y * 2).min(self.height - 1);
+            let x1 = (x * 2 + 1).min(self.width - 1);
+            let y1 = (y * 2 + 1).min(self.height - 1);
+            [
+                x0 + y0 * self.width,
+                x1 + y0 * self.width,
+                x0 + y1 * self.width,
+                x1 + y1 * self.width,
+            ]
+        };
+        let blocks: Vec<[usize; 4]> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| block(x, y))
+            .collect();
+        let pixels = blocks
+            .iter()
+            .map(|b| {
+                b.iter()
+                    .fold(Vector3::zero(), |sum, i| sum.add(self.pixels[*i]))
+                    .multiply(0.25)
+            })
+            .collect();
+        let alpha = self.alpha.as_ref().map(|alpha| {
+            blocks
+                .iter()
+                .map(|b| b.iter().map(|i| alpha[*i]).sum::<f32>() * 0.25)
+                .collect()
+        });
+        Self {
+            width,
+            height,
+            pixels,
+            alpha,
+        }
+    }
+
+    fn index(&self, x: i64, y: i64, wrap: [WrapMode; 2]) -> usize {
+        let x = wrap[0].wrap(x, self.width);
+        let y = wrap[1].wrap(y, self.height);
+        x + y * self.width
+    }
+
+    //Texels surrounding texture coordinates with their bilinear weights, with v pointing up
+    fn taps(&self, uv: [f32; 2], wrap: [WrapMode; 2]) -> [(usize, f32); 4] {
+        let x = uv[0] * self.width as f32 - 0.5;
+        let y = (1f32 - uv[1]) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        [
+            (self.index(x0, y0, wrap), (1f32 - fx) * (1f32 - fy)),
+            (self.index(x0 + 1, y0, wrap), fx * (1f32 - fy)),
+            (self.index(x0, y0 + 1, wrap), (1f32 - fx) * fy),
+            (self.index(x0 + 1, y0 + 1, wrap), fx * fy),
+        ]
+    }
+
+    fn sample(&self, uv: [f32; 2], wrap: [WrapMode; 2]) -> Vector3 {
+        self.taps(uv, wrap)
+            .iter()
+            .fold(Vector3::zero(), |sum, (i, w)| {
+                sum.add(self.pixels[*i].multiply(*w))
+            })
+    }
+
+    //Alpha channel, or the average of the color if there is none
+    fn sample_alpha(&self, uv: [f32; 2], wrap: [WrapMode; 2]) -> f32 {
+        match &self.alpha {
+            Some(alpha) => self.taps(uv, wrap).iter().map(|(i, w)| alpha[*i] * w).sum(),
+            None => self.sample(uv, wrap).average(),
+        }
+    }
+}
+
+//Image with a pyramid of successively halved resolutions, the first level being the full one
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pub levels: Vec<MipLevel>,
+    //Wrapping along u and v
+    pub wrap: [WrapMode; 2],
+}
+
+//Largest ratio between the long and short axis of a filtered footprint
+const MAX_ANISOTROPY: f32 = 8f32;
+
+impl ImageTexture {
+    //Color textures are stored in sRGB and converted to linear values, data textures are not
+    pub fn from_file<P: AsRef<Path>>(
+        filename: P,
+        wrap: [WrapMode; 2],
+        color: bool,
+    ) -> Result<Self, Error> {
+        let filename = filename.as_ref();
+        let image = image::open(filename)
+            .map_err(|e| Error::new(format!("Couldn't load texture {:?}: {}", filename, e)))?;
+        let has_alpha = image.color().has_alpha();
+        let image = image.to_rgba();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                let v = Vector3::new(p[0] as f32, p[1] as f32, p[2] as f32).multiply(1f32 / 255f32);
+                if color {
+                    v.srgb()
+                } else {
+                    v
+                }
+            })
+            .collect();
+        let alpha = if has_alpha {
+            Some(image.pixels().map(|p| p[3] as f32 / 255f32).collect())
+        } else {
+            None
+        };
+        Ok(Self::new(
+            width as usize,
+            height as usize,
+            pixels,
+            alpha,
+            wrap,
+        ))
+    }
+
+    //Image from linear texel values, row by row from the top
+    pub fn new(
+        width: usize,
+        height: usize,
+        pixels: Vec<Vector3>,
+        alpha: Option<Vec<f32>>,
+        wrap: [WrapMode; 2],
+    ) -> Self {
+        let mut levels = vec![MipLevel {
+            width,
+            height,
+            pixels,
+            alpha,
+        }];
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+        Self {
+            width,
+            height,
+            levels,
+            wrap,
+        }
+    }
+
+    //Image with every texel changed by `f`, keeping the alpha channel
+    pub fn mapped<F: Fn(Vector3) -> Vector3>(&self, f: F) -> Self {
+        let level = &self.levels[0];
+        let pixels = level.pixels.iter().map(|p| f(*p)).collect();
+        Self::new(
+            self.width,
+            self.height,
+            pixels,
+            level.alpha.clone(),
+            self.wrap,
+        )
+    }
+
+    //Bilinear lookup at full resolution
+    pub fn sample(&self, uv: [f32; 2]) -> Vector3 {
+        self.levels[0].sample(uv, self.wrap)
+    }
+
+    //Trilinear lookup between the two levels around a fractional level
+    fn sample_level<F>(&self, uv: [f32; 2], level: f32, lookup: &F) -> Vector3
+    where
+        F: Fn(&MipLevel, [f32; 2]) -> Vector3,
+    {
+        let last = self.levels.len() - 1;
+        let level = level.clamp(0f32, last as f32);
+        let lower = level.floor() as usize;
+        let t = level - lower as f32;
+        let color = lookup(&self.levels[lower], uv);
+        if t <= 0f32 || lower == last {
+            return color;
+        }
+        color
+            .multiply(1f32 - t)
+            .add(lookup(&self.levels[lower + 1], uv).multiply(t))
+    }
+
+    //Average over the footprint spanned by the changes of the texture coordinates to the
+    //neighbouring pixels. Elongated footprints are covered by several lookups along their
+    //long axis at the level matching their short one
+    pub fn sample_filtered(&self, uv: [f32; 2], duvdx: [f32; 2], duvdy: [f32; 2]) -> Vector3 {
+        self.filtered(uv, duvdx, duvdy, |level, uv| level.sample(uv, self.wrap))
+    }
+
+    //Alpha channel averaged over a footprint, or the average of the color if there is none
+    pub fn sample_alpha(&self, uv: [f32; 2], duvdx: [f32; 2], duvdy: [f32; 2]) -> f32 {
+        self.filtered(uv, duvdx, duvdy, |level, uv| {
+            Vector3::from_single(level.sample_alpha(uv, self.wrap))
+        })
+        .x
+    }
+
+    fn filtered<F>(&self, uv: [f32; 2], duvdx: [f32; 2], duvdy: [f32; 2], lookup: F) -> Vector3
+    where
+        F: Fn(&MipLevel, [f32; 2]) -> Vector3,
+    {
+        let texels = |d: [f32; 2]| {
+            let x = d[0] * self.width as f32;
+            let y = d[1] * self.height as f32;
+            (x * x + y * y).sqrt()
+        };
+        let (lx, ly) = (texels(duvdx), texels(duvdy));
+        let (major, axis, minor) = if lx > ly {
+            (lx, duvdx, ly)
+        } else {
+            (ly, duvdy, lx)
+        };
+        if major <= 0f32 || !major.is_finite() {
+            return lookup(&self.levels[0], uv);
+        }
+        let minor = minor.max(major / MAX_ANISOTROPY);
+        let level = minor.max(1e-8).log2();
+        let probes = (major / minor).ceil().clamp(1f32, MAX_ANISOTROPY) as usize;
+        (0..probes)
+            .map(|i| {
+                let t = (i as f32 + 0.5) / probes as f32 - 0.5;
+                self.sample_level([uv[0] + axis[0] * t, uv[1] + axis[1] * t], level, &lookup)
+            })
+            .fold(Vector3::zero(), |sum, c| sum.add(c))
+            .multiply(1f32 / probes as f32)
+    }
+
+    pub fn average(&self) -> Vector3 {
+        let pixels = &self.levels[0].pixels;
+        pixels
+            .iter()
+            .fold(Vector3::zero(), |sum, p| sum.add(*p))
+            .multiply(1f32 / pixels.len().max(1) as f32)
+    }
+}
+
+//Change of the position and texture coordinates between the points the neighbouring pixels see,
+//for filtering lookups. All zero for point lookups
+#[derive(Clone, Copy)]
+pub struct Footprint {
+    pub dpdx: Vector3,
+    pub dpdy: Vector3,
+    pub duvdx: [f32; 2],
+    pub duvdy: [f32; 2],
+}
+
+impl Footprint {
+    pub fn point() -> Self {
+        Self {
+            dpdx: Vector3::zero(),
+            dpdy: Vector3::zero(),
+            duvdx: [0f32; 2],
+            duvdy: [0f32; 2],
+        }
+    }
+}
+
+//How the coverage of a surface, its alpha, hides it
+#[derive(Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    //Cut out where the coverage is below the cutoff
+    Mask(f32),
+    //Partially covering, so that rays pass through with a probability of one minus the coverage
+    Blend,
+}
+
+//Textures replacing parameters of a material. Images are shared between materials using the
+//same file
+pub struct MaterialTextures {
+    pub base_color: Option<Texture>,
+    pub specular: Option<Texture>,
+    pub roughness: Option<Texture>,
+    pub metallic: Option<Texture>,
+    pub emission: Option<Texture>,
+    //Tangent space normal map
+    pub normal: Option<Arc<ImageTexture>>,
+    //Coverage of the surface, multiplied by `alpha_factor` and applied by `alpha_mode`
+    pub alpha: Option<Arc<ImageTexture>>,
+    pub alpha_factor: f32,
+    pub alpha_mode: AlphaMode,
+    //Height map, scaled by `bump_strength`
+    pub bump: Option<Arc<ImageTexture>>,
+    pub bump_strength: f32,
+}
+
+pub const ALPHA_CUTOFF: f32 = 0.5;
+
+impl Default for MaterialTextures {
+    fn default() -> Self {
+        Self {
+            base_color: None,
+            specular: None,
+            roughness: None,
+            metallic: None,
+            emission: None,
+            normal: None,
+            alpha: None,
+            alpha_factor: 1f32,
+            alpha_mode: AlphaMode::Mask(ALPHA_CUTOFF),
+            bump: None,
+            bump_strength: 1f32,
+        }
+    }
+}
+
+impl MaterialTextures {
+    pub fn is_empty(&self) -> bool {
+        self.base_color.is_none()
+            && self.specular.is_none()
+            && self.roughness.is_none()
+            && self.metallic.is_none()
+            && self.emission.is_none()
+            && !self.has_coverage()
+            && !self.perturbs_normal()
+    }
+
+    //Whether parts of the surface may be hidden
+    pub fn has_coverage(&self) -> bool {
+        self.alpha.is_some() || self.alpha_factor < 1f32
+    }
+
+    //Whether a ray passes through the surface at the given texture coordinates
+    pub fn masked(&self, uv: [f32; 2], footprint: &Footprint) -> bool {
+        if !self.has_coverage() {
+            return false;
+        }
+        let coverage = match &self.alpha {
+            Some(alpha) => alpha.sample_alpha(uv, footprint.duvdx, footprint.duvdy),
+            None => 1f32,
+        } * self.alpha_factor;
+        match self.alpha_mode {
+            AlphaMode::Mask(cutoff) => coverage < cutoff,
+            AlphaMode::Blend => rand::random::<f32>() >= coverage,
+        }
+    }
+
+    pub fn perturbs_normal(&self) -> bool {
+        self.normal.is_some() || self.bump.is_some()
+    }
+
+    //Perturbs the shading normal, averaging the maps over the footprint. `tangent` and
+    //`bitangent` are the derivatives of the position with respect to the texture coordinates
+    pub fn perturb_normal(
+        &self,
+        uv: [f32; 2],
+        footprint: &Footprint,
+        normal: Vector3,
+        tangent: Vector3,
+        bitangent: Vector3,
+    ) -> Vector3 {
+        let mut normal = normal;
+        if let Some(texture) = &self.normal {
+            let n = texture
+                .sample_filtered(uv, footprint.duvdx, footprint.duvdy)
+                .multiply(2f32)
+                .add_scalar(-1f32);
+            let perturbed = tangent
+                .normalized()
+                .multiply(n.x)
+                .add(bitangent.normalized().multiply(n.y))
+                .add(normal.multiply(n.z))
+                .normalized();
+            if perturbed.x.is_finite() {
+                normal = perturbed;
+            }
+        }
+        if let Some(texture) = &self.bump {
+            //Central differences one texel or half the footprint apart, whichever is wider
+            let (dx, dy) = (footprint.duvdx, footprint.duvdy);
+            let du = (1f32 / texture.width as f32).max((dx[0].abs() + dy[0].abs()) * 0.5);
+            let dv = (1f32 / texture.height as f32).max((dx[1].abs() + dy[1].abs()) * 0.5);
+            let height = |u: f32, v: f32| {
+                texture.sample_filtered([u, v], dx, dy).average() * self.bump_strength
+            };
+            let hu = (height(uv[0] + du, uv[1]) - height(uv[0] - du, uv[1])) / (2f32 * du);
+            let hv = (height(uv[0], uv[1] + dv) - height(uv[0], uv[1] - dv)) / (2f32 * dv);
+            //Normal of the surface displaced along the normal by the height
+            let perturbed = tangent
+                .add(normal.multiply(hu))
+                .cross(bitangent.add(normal.multiply(hv)))
+                .normalized();
+            let perturbed = if perturbed.dot(normal) < 0f32 {
+                perturbed.multiply(-1f32)
+            } else {
+                perturbed
+            };
+            if perturbed.x.is_finite() {
+                normal = perturbed;
+            }
+        }
+        normal
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use crate::{
+    noise,
+    utilities::{Matrix3, Vector3},
+};
+
+use super::{Footprint, ImageTexture};
+
+//Where a texture graph is evaluated
+#[derive(Clone, Copy, PartialEq)]
+pub enum Coordinates {
+    //World space position of the hit
+    Position,
+    //Texture coordinates as (u, v, 0)
+    Uv,
+}
+
+//Node of a texture graph. Nodes producing patterns are evaluated at a point which starts out
+//at the graph's coordinates and is changed by `Transform`
+#[derive(Clone)]
+pub enum TextureNode {
+    Constant(Vector3),
+    //Sampled at the x and y of the point
+    Image(Arc<ImageTexture>),
+    //Alternates between two nodes in unit cells
+    Checker(Box<TextureNode>, Box<TextureNode>),
+    //Fractal Perlin noise remapped to [0, 1]
+    Noise {
+        octaves: u32,
+    },
+    //Distance to the closest of randomly placed points, one in every unit cell
+    Voronoi,
+    //Goes from 0 to 1 along `direction`, starting at the origin
+    Gradient {
+        direction: Vector3,
+    },
+    //Blends from the first node to the second by the average of `factor`
+    Mix {
+        a: Box<TextureNode>,
+        b: Box<TextureNode>,
+        factor: Box<TextureNode>,
+    },
+    //Component-wise product of two nodes
+    Multiply(Box<TextureNode>, Box<TextureNode>),
+    //Single channel of a node, 0 to 2 for red to blue, in every channel
+    Channel {
+        input: Box<TextureNode>,
+        channel: usize,
+    },
+    //Evaluates `input` at the point transformed by `matrix` and moved by `offset`
+    Transform {
+        input: Box<TextureNode>,
+        matrix: Matrix3,
+        offset: Vector3,
+    },
+}
+
+//Random position within a cell
+fn feature_point(x: i32, y: i32, z: i32) -> Vector3 {
+    let h = noise::hash(x, y, z);
+    let component = |shift: u32| ((h >> shift) & 0x3ff) as f32 / 1023f32;
+    Vector3::new(x as f32, y as f32, z as f32).add(Vector3::new(
+        component(0),
+        component(10),
+        component(20),
+    ))
+}
+
+//Integral of the square wave that is 1 on even and -1 on odd unit intervals
+fn square_wave_integral(x: f32) -> f32 {
+    1f32 - (x.rem_euclid(2f32) - 1f32).abs()
+}
+
+//Average of the square wave over `x` plus or minus `width`
+fn filtered_square_wave(x: f32, width: f32) -> f32 {
+    if width < 1e-6 {
+        if x.floor().rem_euclid(2f32) == 0f32 {
+            1f32
+        } else {
+            -1f32
+        }
+    } else {
+        (square_wave_integral(x + width) - square_wave_integral(x - width)) / (2f32 * width)
+    }
+}
+
+//Sum of the first `octaves` amplitudes of fractal noise
+fn fbm_amplitude(octaves: f32) -> f32 {
+    2f32 * (1f32 - 0.5f32.powf(octaves))
+}
+
+impl TextureNode {
+    pub fn constant(color: Vector3) -> Self {
+        TextureNode::Constant(color)
+    }
+
+    pub fn image(image: Arc<ImageTexture>) -> Self {
+        TextureNode::Image(image)
+    }
+
+    pub fn checker(a: TextureNode, b: TextureNode) -> Self {
+        TextureNode::Checker(Box::new(a), Box::new(b))
+    }
+
+    pub fn mix(a: TextureNode, b: TextureNode, factor: TextureNode) -> Self {
+        TextureNode::Mix {
+            a: Box::new(a),
+            b: Box::new(b),
+            factor: Box::new(factor),
+        }
+    }
+
+    pub fn multiply(a: TextureNode, b: TextureNode) -> Self {
+        TextureNode::Multiply(Box::new(a), Box::new(b))
+    }
+
+    pub fn channel(input: TextureNode, channel: usize) -> Self {
+        TextureNode::Channel {
+            input: Box::new(input),
+            channel,
+        }
+    }
+
+    //Evaluates the node at `scale` times the point plus `offset`
+    pub fn scaled(self, scale: Vector3, offset: Vector3) -> Self {
+        TextureNode::Transform {
+            input: Box::new(self),
+            matrix: Matrix3::new(
+                Vector3::new(scale.x, 0f32, 0f32),
+                Vector3::new(0f32, scale.y, 0f32),
+                Vector3::new(0f32, 0f32, scale.z),
+            ),
+            offset,
+        }
+    }
+
+    //Value of the node at `p`, averaged over the footprint spanned by `dpdx` and `dpdy`.
+    //Patterns without a closed form average are point sampled
+    pub fn evaluate(&self, p: Vector3, dpdx: Vector3, dpdy: Vector3) -> Vector3 {
+        match self {
+            TextureNode::Constant(color) => *color,
+            TextureNode::Image(image) => {
+                image.sample_filtered([p.x, p.y], [dpdx.x, dpdx.y], [dpdy.x, dpdy.y])
+            }
+            TextureNode::Checker(a, b) => {
+                //The checker is the product of a square wave along every axis, which
+                //a box filter averages separately
+                let width = dpdx.abs().add(dpdy.abs()).multiply(0.5);
+                let sign = filtered_square_wave(p.x, width.x)
+                    * filtered_square_wave(p.y, width.y)
+                    * filtered_square_wave(p.z, width.z);
+                let t = (1f32 - sign) * 0.5;
+                let a = if t < 1f32 {
+                    a.evaluate(p, dpdx, dpdy).multiply(1f32 - t)
+                } else {
+                    Vector3::zero()
+                };
+                let b = if t > 0f32 {
+                    b.evaluate(p, dpdx, dpdy).multiply(t)
+                } else {
+                    Vector3::zero()
+                };
+                a.add(b)
+            }
+            TextureNode::Noise { octaves } => {
+                //Octaves finer than the footprint average out to zero
+                let width = dpdx.length().max(dpdy.length());
+                let limit = if width > 0f32 {
+                    (1f32 - width.log2()).max(1f32)
+                } else {
+                    f32::INFINITY
+                };
+                let kept = (*octaves).max(1).min(limit as u32);
+                let scale = fbm_amplitude(kept as f32) / fbm_amplitude((*octaves).max(1) as f32);
+                let value = noise::fbm(p, kept) * scale;
+                Vector3::from_single((value * 0.5 + 0.5).clamp(0f32, 1f32))
+            }
+            TextureNode::Voronoi => {
+                let (x, y, z) = (p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
+                let mut closest = f32::INFINITY;
+                for dz in -1..=1 {
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            let feature = feature_point(x + dx, y + dy, z + dz);
+                            closest = closest.min(feature.subtract(p).length_squared());
+                        }
+                    }
+                }
+                Vector3::from_single(closest.sqrt().min(1f32))
+            }
+            TextureNode::Gradient { direction } => {
+                Vector3::from_single(p.dot(*direction).clamp(0f32, 1f32))
+            }
+            TextureNode::Mix { a, b, factor } => {
+                let t = factor.evaluate(p, dpdx, dpdy).average().clamp(0f32, 1f32);
+                a.evaluate(p, dpdx, dpdy)
+                    .multiply(1f32 - t)
+                    .add(b.evaluate(p, dpdx, dpdy).multiply(t))
+            }
+            TextureNode::Multiply(a, b) => a
+                .evaluate(p, dpdx, dpdy)
+                .comp_multiply(b.evaluate(p, dpdx, dpdy)),
+            TextureNode::Channel { input, channel } => {
+                let color = input.evaluate(p, dpdx, dpdy);
+                Vector3::from_single([color.x, color.y, color.z][*channel])
+            }
+            TextureNode::Transform {
+                input,
+                matrix,
+                offset,
+            } => input.evaluate(
+                matrix.multiply(p).add(*offset),
+                matrix.multiply(dpdx),
+                matrix.multiply(dpdy),
+            ),
+        }
+    }
+}
+
+//Texture graph along with the coordinates it is evaluated at
+#[derive(Clone)]
+pub struct Texture {
+    pub node: TextureNode,
+    pub coordinates: Coordinates,
+}
+
+impl Texture {
+    pub fn new(node: TextureNode, coordinates: Coordinates) -> Self {
+        Self { node, coordinates }
+    }
+
+    //Image mapped by texture coordinates
+    pub fn image(image: Arc<ImageTexture>) -> Self {
+        Self::new(TextureNode::image(image), Coordinates::Uv)
+    }
+
+    pub fn evaluate(&self, position: Vector3, uv: [f32; 2], footprint: &Footprint) -> Vector3 {
+        match self.coordinates {
+            Coordinates::Position => self.node.evaluate(position, footprint.dpdx, footprint.dpdy),
+            Coordinates::Uv => {
+                let uv_vector = |uv: [f32; 2]| Vector3::new(uv[0], uv[1], 0f32);
+                self.node.evaluate(
+                    uv_vector(uv),
+                    uv_vector(footprint.duvdx),
+                    uv_vector(footprint.duvdy),
+                )
+            }
+        }
+    }
+}
@@ -183,6 +183,16 @@ impl Vector3 {
         }
     }
 
+    //Cosine weighted direction around the normal
+    pub fn random_cosine_hemisphere(normal: Self) -> Self {
+        let p = normal.add(Self::random_on_sphere());
+        if p.length_squared() < 1e-8 {
+            normal
+        } else {
+            p.normalized()
+        }
+    }
+
     pub fn comp_multiply(&self, a: Self) -> Self {
         Self::new(self.x * a.x, self.y * a.y, self.z * a.z)
     }
@@ -195,6 +205,18 @@ impl Vector3 {
         Self::new(self.x.powf(a), self.y.powf(a), self.z.powf(a))
     }
 
+    pub fn exp(&self) -> Self {
+        Self::new(self.x.exp(), self.y.exp(), self.z.exp())
+    }
+
+    pub fn ln(&self) -> Self {
+        Self::new(self.x.ln(), self.y.ln(), self.z.ln())
+    }
+
+    pub fn average(&self) -> f32 {
+        (self.x + self.y + self.z) / 3f32
+    }
+
     pub fn cross(&self, a: Self) -> Self {
         let x = self.y * a.z - self.z * a.y;
         let y = self.z * a.x - self.x * a.z;
@@ -209,16 +231,18 @@ impl Vector3 {
     pub fn min(&self, a: Self) -> Self {
         Self::new(self.x.min(a.x), self.y.min(a.y), self.z.min(a.z))
     }
-    //TODO: Verify accuracy
-    pub fn refract(&self, n: Self, eta: f32) -> Self {
+    //`eta` is the incident IOR over the transmitted IOR, `n` faces the incoming ray.
+    //Returns None on total internal reflection
+    pub fn refract(&self, n: Self, eta: f32) -> Option<Self> {
         let dotni = self.dot(n);
         let k = 1f32 - eta * eta * (1f32 - dotni * dotni);
         if k < 0f32 {
-            return Vector3::zero();
+            None
         } else {
-            return self
-                .multiply(eta)
-                .subtract(n.multiply(k.sqrt() + eta * self.dot(n)));
+            Some(
+                self.multiply(eta)
+                    .subtract(n.multiply(k.sqrt() + eta * dotni)),
+            )
         }
     }
 
@@ -235,8 +259,19 @@ impl Vector3 {
 pub struct SceneData {
     pub camera_position: Vector3,
     pub camera_target: Vector3,
+    //Distance to the image plane, which spans -1 to 1 vertically
+    pub fov: f32,
+    //Mean free path in fog at its nominal density
     pub fog_amount: f32,
     pub fog: bool,
+    //Fraction of fog interactions that scatter rather than absorb
+    pub fog_albedo: Vector3,
+    //Henyey-Greenstein asymmetry of fog scattering, positive values scatter forward
+    pub fog_anisotropy: f32,
+    //Rate at which fog density falls off with height, 0 for uniform fog
+    pub fog_falloff: f32,
+    //Height at which fog has its nominal density
+    pub fog_height: f32,
 }
 
 impl SceneData {
@@ -249,6 +284,7 @@ impl SceneData {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Matrix3 {
     i: Vector3,
     j: Vector3,
@@ -275,4 +311,19 @@ impl Matrix3 {
         let up = right.cross(dir).multiply(-1f32);
         Self::new(right, up, dir)
     }
+
+    //Orthonormal basis with the normal as its Z axis
+    pub fn from_normal(n: Vector3) -> Self {
+        let sign = 1f32.copysign(n.z);
+        let a = -1f32 / (sign + n.z);
+        let b = n.x * n.y * a;
+        let tangent = Vector3::new(1f32 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+        let bitangent = Vector3::new(b, sign + n.y * n.y * a, -n.y);
+        Self::new(tangent, bitangent, n)
+    }
+
+    //Inverse of multiply for orthonormal matrices
+    pub fn transpose_multiply(&self, a: Vector3) -> Vector3 {
+        Vector3::new(self.i.dot(a), self.j.dot(a), self.k.dot(a))
+    }
 }